@@ -0,0 +1,70 @@
+use std::{fs, path::Path};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sg_syntax::{DocumentGenerator, LimitConfig};
+use syntect::parsing::SyntaxSet;
+
+/// A Go file with `functions` copies of a small function, to see how
+/// highlighting scales with the number of lines.
+fn large_file(functions: usize) -> String {
+    let mut code = String::from("package main\n\nimport \"fmt\"\n");
+    for i in 0..functions {
+        code.push_str(&format!(
+            "\n// f{i} prints its argument.\nfunc f{i}(x int) string {{\n\tif x > {i} {{\n\t\treturn fmt.Sprintf(\"%d\", x)\n\t}}\n\treturn \"\"\n}}\n",
+            i = i
+        ));
+    }
+    code
+}
+
+fn bench_fixtures(c: &mut Criterion) {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/sg_syntect_document/snapshots/syntect_files");
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+
+    let mut group = c.benchmark_group("fixtures");
+    for path in files {
+        let code = fs::read_to_string(&path).unwrap();
+        let extension = path.extension().unwrap().to_str().unwrap();
+        let syntax_reference = syntax_set.find_syntax_by_extension(extension).unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+
+        group.throughput(Throughput::Bytes(code.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &code, |b, code| {
+            b.iter(|| {
+                DocumentGenerator::new(&syntax_set, syntax_reference, code, LimitConfig::default())
+                    .generate()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_large_file(c: &mut Criterion) {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let syntax_reference = syntax_set.find_syntax_by_extension("go").unwrap();
+
+    let mut group = c.benchmark_group("large_file");
+    group.sample_size(10);
+    for functions in [100, 1_000, 10_000] {
+        let code = large_file(functions);
+        group.throughput(Throughput::Bytes(code.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(functions), &code, |b, code| {
+            b.iter(|| {
+                DocumentGenerator::new(&syntax_set, syntax_reference, code, LimitConfig::default())
+                    .generate()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_fixtures, bench_large_file);
+criterion_main!(benches);
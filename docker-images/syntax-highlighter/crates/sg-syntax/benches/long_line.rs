@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sg_syntax::{DocumentGenerator, LimitConfig};
+use syntect::parsing::SyntaxSet;
+
+/// A single line of Go with `tokens` binary expressions, which is the shape of
+/// minified or generated code that ends up with thousands of ops per line.
+fn long_line(tokens: usize) -> String {
+    let mut line = String::from("x := 0");
+    for i in 0..tokens {
+        line.push_str(&format!(" + a{}", i));
+    }
+    line.push('\n');
+    line
+}
+
+fn bench_long_line(c: &mut Criterion) {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let syntax_reference = syntax_set.find_syntax_by_extension("go").unwrap();
+
+    let mut group = c.benchmark_group("long_line");
+    for tokens in [100, 1_000, 10_000] {
+        let code = long_line(tokens);
+        group.throughput(Throughput::Bytes(code.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(tokens), &code, |b, code| {
+            b.iter(|| {
+                DocumentGenerator::new(&syntax_set, syntax_reference, code, LimitConfig::default())
+                    .generate()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_long_line);
+criterion_main!(benches);
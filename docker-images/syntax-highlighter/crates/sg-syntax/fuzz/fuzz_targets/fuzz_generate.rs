@@ -0,0 +1,31 @@
+//! Run with `cargo +nightly fuzz run fuzz_generate` from `crates/sg-syntax`.
+//! The seeds in `corpus/fuzz_generate` cover some tricky inputs (CRLF,
+//! multibyte characters, unterminated blocks, very long lines).
+
+#![no_main]
+
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+use sg_syntax::{DocumentGenerator, LimitConfig};
+use syntect::parsing::SyntaxSet;
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
+// The first byte picks the syntax, the rest is the code. Generating a
+// document must never panic, whatever the input is.
+fuzz_target!(|data: &[u8]| {
+    let (syntax, code) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let code = match std::str::from_utf8(code) {
+        Ok(code) => code,
+        Err(_) => return,
+    };
+
+    let syntaxes = SYNTAX_SET.syntaxes();
+    let sr = &syntaxes[*syntax as usize % syntaxes.len()];
+    let _ = DocumentGenerator::new(&SYNTAX_SET, sr, code, LimitConfig::default()).generate();
+});
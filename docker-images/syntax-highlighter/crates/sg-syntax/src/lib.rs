@@ -6,21 +6,40 @@ use syntect::html::{highlighted_html_for_string, ClassStyle};
 use syntect::{
     highlighting::ThemeSet,
     parsing::{SyntaxReference, SyntaxSet},
+    LoadingError,
 };
 
 mod sg_treesitter;
+pub use sg_treesitter::document_to_ansi;
+pub use sg_treesitter::document_to_scip_bytes;
+pub use sg_treesitter::document_to_semantic_tokens;
 pub use sg_treesitter::dump_document;
+pub use sg_treesitter::dump_document_compact;
+pub use sg_treesitter::dump_document_json;
 pub use sg_treesitter::dump_document_range;
 pub use sg_treesitter::index_language as lsif_index;
 pub use sg_treesitter::index_language_with_config as lsif_index_with_config;
 pub use sg_treesitter::lsif_highlight;
+pub use sg_treesitter::lsif_highlight_with_syntax_set;
 pub use sg_treesitter::make_highlight_config;
+pub use sg_treesitter::scip_bytes_to_document;
 pub use sg_treesitter::FileRange as DocumentFileRange;
 pub use sg_treesitter::PackedRange as LsifPackedRange;
+pub use sg_treesitter::{SEMANTIC_TOKEN_MODIFIERS, SEMANTIC_TOKEN_TYPES};
 
 mod sg_syntect;
 use sg_syntect::ClassedTableGenerator;
 
+mod sg_syntect_document;
+pub use sg_syntect_document::{
+    assert_non_overlapping, load_scope_mapping, merge_documents, Checkpoint, ColumnEncoding,
+    DocumentGenerator, GenerateStats, HighlightEngine, HighlightError, LanguageError, LimitConfig,
+    ScopeMappingError,
+};
+
+mod sg_diff;
+pub use sg_diff::{DiffDocumentGenerator, DiffLineKind};
+
 thread_local! {
     pub(crate) static SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
 }
@@ -32,7 +51,7 @@ lazy_static::lazy_static! {
 /// Struct from: internal/gosyntect/gosyntect.go
 ///
 /// Keep in sync with that struct.
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct SourcegraphQuery {
     // Deprecated field with a default empty string value, kept for backwards
     // compatability with old clients.
@@ -61,14 +80,31 @@ pub struct SourcegraphQuery {
     // theme is ignored if css is true
     pub theme: String,
 
+    // If no language matches the query, highlight the code as plain text
+    // (producing no occurrences) instead of returning an error.
+    #[serde(default)]
+    pub fallback_plain_text: bool,
+
+    // If set, a vim modeline like `# vim: set ft=ruby:` in the first or last
+    // lines of the code overrides the language of the filepath. See
+    // `modeline_filetype`.
+    #[serde(default)]
+    pub respect_modelines: bool,
+
     pub code: String,
 }
 
 pub fn determine_filetype(q: &SourcegraphQuery) -> String {
-    let filetype = SYNTAX_SET.with(|syntax_set| match determine_language(q, syntax_set) {
+    SYNTAX_SET.with(|syntax_set| determine_filetype_with_syntax_set(q, syntax_set))
+}
+
+/// Like `determine_filetype`, but detects the language with the syntaxes of
+/// `syntax_set` instead of the default ones.
+pub fn determine_filetype_with_syntax_set(q: &SourcegraphQuery, syntax_set: &SyntaxSet) -> String {
+    let filetype = match determine_language(q, syntax_set) {
         Ok(language) => language.name.clone(),
         Err(_) => "".to_owned(),
-    });
+    };
 
     // We normalize all the filenames here
     match filetype.as_str() {
@@ -78,12 +114,25 @@ pub fn determine_filetype(q: &SourcegraphQuery) -> String {
     .to_lowercase()
 }
 
+/// Picks the syntax for a query, trying in order:
+///
+/// 1. the explicit `filetype`,
+/// 2. a vim modeline in `code`, if the query sets `respect_modelines`,
+/// 3. the file name and extension of `filepath` (including the overrides for
+///    conflicting extensions below),
+/// 4. the legacy `extension` field,
+/// 5. the interpreter of a shebang like `#!/usr/bin/env python`,
+/// 6. the first line of `code`, using the syntaxes' own first line patterns.
+///
+/// If nothing matches, this falls back to plain text when a `filepath` was
+/// given, and returns an error otherwise.
 fn determine_language<'a>(
     q: &SourcegraphQuery,
     syntax_set: &'a SyntaxSet,
 ) -> Result<&'a SyntaxReference, JsonValue> {
     // If filetype is passed, we should choose that if possible.
     if let Some(filetype) = &q.filetype {
+        let filetype = normalize_filetype(filetype);
         // This is `find_syntax_by_name` except that it doesn't care about
         // case sensitivity or anything like that.
         //
@@ -93,24 +142,204 @@ fn determine_language<'a>(
             .syntaxes()
             .iter()
             .rev()
-            .find(|&s| filetype == &s.name.to_lowercase())
+            .find(|&s| filetype == s.name.to_lowercase())
         {
             return Ok(language);
         }
     }
 
-    if q.filepath.is_empty() {
-        // Legacy codepath, kept for backwards-compatability with old clients.
-        return match syntax_set.find_syntax_by_extension(&q.extension) {
-            Some(v) => Ok(v),
-            // Fall back: Determine syntax definition by first line.
-            None => match syntax_set.find_syntax_by_first_line(&q.code) {
-                Some(v) => Ok(v),
-                None => Err(json!({"error": "invalid extension"})),
-            },
-        };
+    if q.respect_modelines {
+        if let Some(language) = modeline_filetype(&q.code)
+            .and_then(|filetype| syntax_set.find_syntax_by_token(&normalize_filetype(filetype)))
+        {
+            return Ok(language);
+        }
+    }
+
+    if !q.filepath.is_empty() {
+        if let Some(language) = determine_language_by_filepath(q, syntax_set) {
+            return Ok(language);
+        }
+    }
+
+    // Legacy field, kept for backwards-compatability with old clients.
+    if let Some(language) = syntax_set.find_syntax_by_extension(&q.extension) {
+        return Ok(language);
+    }
+
+    if let Some(language) =
+        shebang_language(&q.code).and_then(|name| syntax_set.find_syntax_by_name(name))
+    {
+        return Ok(language);
+    }
+
+    match syntax_set.find_syntax_by_first_line(&q.code) {
+        Some(language) => Ok(language),
+        None if q.filepath.is_empty() => Err(json!({"error": "invalid extension"})),
+        None => Ok(syntax_set.find_syntax_plain_text()),
+    }
+}
+
+/// The language that `determine_language` picked for a query, for logging
+/// and for showing it in the UI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageInfo {
+    pub name: String,
+    /// The top-level scope of the syntax, like `source.go`.
+    pub scope: String,
+    pub file_extensions: Vec<String>,
+    pub first_line_match: Option<String>,
+}
+
+impl From<&SyntaxReference> for LanguageInfo {
+    fn from(syntax: &SyntaxReference) -> Self {
+        LanguageInfo {
+            name: syntax.name.clone(),
+            scope: syntax.scope.build_string(),
+            file_extensions: syntax.file_extensions.clone(),
+            first_line_match: syntax.first_line_match.clone(),
+        }
+    }
+}
+
+/// Like `determine_language`, but returns a description of the language
+/// instead of the syntax, or None if no language matches.
+pub fn detect_language_info(q: &SourcegraphQuery, syntax_set: &SyntaxSet) -> Option<LanguageInfo> {
+    determine_language(q, syntax_set)
+        .ok()
+        .map(LanguageInfo::from)
+}
+
+/// Line prefixes that give away the language of a snippet, used by
+/// `detect_syntax` when a snippet has no shebang or other first line marker.
+const SNIPPET_HINTS: &[(&str, &str)] = &[
+    ("def ", "Python"),
+    ("elif ", "Python"),
+    ("from ", "Python"),
+    ("func ", "Go"),
+    ("package ", "Go"),
+    ("fn ", "Rust"),
+    ("let mut ", "Rust"),
+    ("#include ", "C"),
+    ("cd ", "Bourne Again Shell (bash)"),
+    ("echo ", "Bourne Again Shell (bash)"),
+    ("export ", "Bourne Again Shell (bash)"),
+];
+
+/// Detects the syntax of a snippet of code that has no file name, e.g. from
+/// a chat message, falling back to plain text.
+///
+/// This tries the shebang and syntect's first line patterns (like
+/// `determine_language`), and then looks for lines that start with a prefix
+/// that is typical for a language.
+pub fn detect_syntax<'a>(syntax_set: &'a SyntaxSet, code: &str) -> &'a SyntaxReference {
+    if let Some(language) =
+        shebang_language(code).and_then(|name| syntax_set.find_syntax_by_name(name))
+    {
+        return language;
+    }
+    if let Some(language) = syntax_set.find_syntax_by_first_line(code) {
+        return language;
+    }
+
+    code.lines()
+        .map(str::trim_start)
+        .find_map(|line| {
+            SNIPPET_HINTS
+                .iter()
+                .find(|(prefix, _)| line.starts_with(prefix))
+        })
+        .and_then(|(_, name)| syntax_set.find_syntax_by_name(name))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text())
+}
+
+/// Generates the `Document` for a snippet of code, using the syntax from
+/// `detect_syntax`.
+pub fn highlight_autodetect(
+    syntax_set: &SyntaxSet,
+    code: &str,
+) -> Result<sg_lsif::Document, HighlightError> {
+    DocumentGenerator::new(
+        syntax_set,
+        detect_syntax(syntax_set, code),
+        code,
+        LimitConfig::default(),
+    )
+    .generate()
+}
+
+/// Lowercases and trims `filetype`, and resolves common aliases to the
+/// (lowercased) name of the syntax.
+fn normalize_filetype(filetype: &str) -> String {
+    let filetype = filetype.trim().to_lowercase();
+    match filetype.as_str() {
+        "golang" => "go".to_string(),
+        "cpp" => "c++".to_string(),
+        "text" => "plain text".to_string(),
+        _ => filetype,
+    }
+}
+
+/// Returns the name of the syntax for the interpreter in the shebang on the
+/// first line of `code`, like `#!/bin/bash` or `#!/usr/bin/env python3`.
+fn shebang_language(code: &str) -> Option<&'static str> {
+    let first_line = code.lines().next()?;
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+
+    let mut interpreter = Path::new(words.next()?).file_name()?.to_str()?;
+    if interpreter == "env" {
+        interpreter = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    // Ignore versions, e.g. `python3` or `ruby2.7`.
+    match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "bash" | "sh" => Some("Bourne Again Shell (bash)"),
+        "python" => Some("Python"),
+        "node" => Some("JavaScript"),
+        "ruby" => Some("Ruby"),
+        "perl" => Some("Perl"),
+        _ => None,
     }
+}
+
+/// The number of lines at the start and at the end of a file that are
+/// searched for a modeline, which is vim's default.
+const MODELINES: usize = 5;
+
+/// Returns the filetype that a vim modeline in the first or last `MODELINES`
+/// lines of `code` sets, e.g. `ruby` for `# vim: set ft=ruby:` or
+/// `// vi: syntax=go`.
+fn modeline_filetype(code: &str) -> Option<&str> {
+    let head = code.lines().take(MODELINES);
+    let tail = code.lines().rev().take(MODELINES);
+    head.chain(tail).find_map(|line| {
+        // The marker must start a word, so that e.g. `index: 1` isn't one.
+        let options = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+            line.match_indices(marker)
+                .find(|(i, _)| {
+                    line[..*i]
+                        .chars()
+                        .next_back()
+                        .map_or(true, char::is_whitespace)
+                })
+                .map(|(i, _)| &line[i + marker.len()..])
+        })?;
 
+        options
+            .split(|c: char| c.is_whitespace() || c == ':')
+            .find_map(|option| match option.split_once('=')? {
+                ("ft" | "filetype" | "syn" | "syntax", filetype) if !filetype.is_empty() => {
+                    Some(filetype)
+                }
+                _ => None,
+            })
+    })
+}
+
+fn determine_language_by_filepath<'a>(
+    q: &SourcegraphQuery,
+    syntax_set: &'a SyntaxSet,
+) -> Option<&'a SyntaxReference> {
     // Split the input path ("foo/myfile.go") into file name
     // ("myfile.go") and extension ("go").
     let path = Path::new(&q.filepath);
@@ -143,23 +372,37 @@ fn determine_language<'a>(
             Some((_, lang)) => lang,
             None => default,
         };
-        return Ok(syntax_set
-            .find_syntax_by_name(name)
-            .unwrap_or_else(|| syntax_set.find_syntax_plain_text()));
+        return Some(
+            syntax_set
+                .find_syntax_by_name(name)
+                .unwrap_or_else(|| syntax_set.find_syntax_plain_text()),
+        );
     }
 
-    Ok(syntax_set
+    syntax_set
         // First try to find a syntax whose "extension" matches our file
         // name. This is done due to some syntaxes matching an "extension"
         // that is actually a whole file name (e.g. "Dockerfile" or "CMakeLists.txt")
         // see https://github.com/trishume/syntect/pull/170
         .find_syntax_by_extension(file_name)
         .or_else(|| syntax_set.find_syntax_by_extension(extension))
-        .or_else(|| syntax_set.find_syntax_by_first_line(&q.code))
-        .unwrap_or_else(|| syntax_set.find_syntax_plain_text()))
 }
 
-pub fn list_features() {
+/// Returns the sorted filetypes that the syntaxes in `syntax_set` can
+/// highlight. These are the lowercased syntax names, which are accepted as
+/// the `filetype` of a `SourcegraphQuery`.
+pub fn supported_filetypes(syntax_set: &SyntaxSet) -> Vec<String> {
+    let mut filetypes: Vec<String> = syntax_set
+        .syntaxes()
+        .iter()
+        .map(|s| s.name.to_lowercase())
+        .collect();
+    filetypes.sort();
+    filetypes.dedup();
+    filetypes
+}
+
+pub fn list_features(syntax_set: &SyntaxSet) {
     // List embedded themes.
     println!("## Embedded themes:");
     println!();
@@ -169,57 +412,77 @@ pub fn list_features() {
     println!();
 
     // List supported file extensions.
-    SYNTAX_SET.with(|syntax_set| {
-        println!("## Supported file extensions:");
-        println!();
-        for sd in syntax_set.syntaxes() {
-            println!("- {} (`{}`)", sd.name, sd.file_extensions.join("`, `"));
+    println!("## Supported file extensions:");
+    println!();
+    for sd in syntax_set.syntaxes() {
+        println!("- {} (`{}`)", sd.name, sd.file_extensions.join("`, `"));
+    }
+    println!();
+}
+
+/// Loads the default syntaxes, plus any syntaxes in `extra_syntaxes_dir`.
+///
+/// Syntaxes are always loaded in their "newlines" variant, since the
+/// highlighters pass every line to syntect together with its line ending.
+/// Syntaxes that expect lines without newlines must not be used here, they
+/// silently produce wrong highlights.
+pub fn load_syntax_set(extra_syntaxes_dir: Option<&Path>) -> Result<SyntaxSet, LoadingError> {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    match extra_syntaxes_dir {
+        Some(dir) => {
+            let mut builder = syntax_set.into_builder();
+            builder.add_from_folder(dir, true)?;
+            Ok(builder.build())
         }
-        println!();
-    });
+        None => Ok(syntax_set),
+    }
 }
 
 pub fn syntect_highlight(q: SourcegraphQuery) -> JsonValue {
-    SYNTAX_SET.with(|syntax_set| {
-        // Determine syntax definition by extension.
-        let syntax_def = match determine_language(&q, syntax_set) {
-            Ok(v) => v,
-            Err(e) => return e,
-        };
+    SYNTAX_SET.with(|syntax_set| syntect_highlight_with_syntax_set(q, syntax_set))
+}
 
-        if q.css {
-            let output = ClassedTableGenerator::new(
-                syntax_set,
-                syntax_def,
-                &q.code,
-                q.line_length_limit,
-                ClassStyle::SpacedPrefixed { prefix: "hl-" },
-            )
-            .generate();
-
-            json!({
-                "data": output,
-                "plaintext": syntax_def.name == "Plain Text",
-            })
-        } else {
-            // TODO(slimsag): return the theme's background color (and other info??) to caller?
-            // https://github.com/trishume/syntect/blob/c8b47758a3872d478c7fc740782cd468b2c0a96b/examples/synhtml.rs#L24
-
-            // Determine theme to use.
-            //
-            // TODO(slimsag): We could let the query specify the theme file's actual
-            // bytes? e.g. via `load_from_reader`.
-            let theme = match THEME_SET.themes.get(&q.theme) {
-                Some(v) => v,
-                None => return json!({"error": "invalid theme", "code": "invalid_theme"}),
-            };
+/// Like `syntect_highlight`, but with the syntaxes of `syntax_set` instead of
+/// the default ones, e.g. a set built once by `load_syntax_set` at startup.
+pub fn syntect_highlight_with_syntax_set(q: SourcegraphQuery, syntax_set: &SyntaxSet) -> JsonValue {
+    // Determine syntax definition by extension.
+    let syntax_def = match determine_language(&q, syntax_set) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
 
-            json!({
-                "data": highlighted_html_for_string(&q.code, syntax_set, syntax_def, theme),
-                "plaintext": syntax_def.name == "Plain Text",
-            })
-        }
-    })
+    if q.css {
+        let output = ClassedTableGenerator::new(
+            syntax_set,
+            syntax_def,
+            &q.code,
+            q.line_length_limit,
+            ClassStyle::SpacedPrefixed { prefix: "hl-" },
+        )
+        .generate();
+
+        json!({
+            "data": output,
+            "plaintext": syntax_def.name == "Plain Text",
+        })
+    } else {
+        // TODO(slimsag): return the theme's background color (and other info??) to caller?
+        // https://github.com/trishume/syntect/blob/c8b47758a3872d478c7fc740782cd468b2c0a96b/examples/synhtml.rs#L24
+
+        // Determine theme to use.
+        //
+        // TODO(slimsag): We could let the query specify the theme file's actual
+        // bytes? e.g. via `load_from_reader`.
+        let theme = match THEME_SET.themes.get(&q.theme) {
+            Some(v) => v,
+            None => return json!({"error": "invalid theme", "code": "invalid_theme"}),
+        };
+
+        json!({
+            "data": highlighted_html_for_string(&q.code, syntax_set, syntax_def, theme),
+            "plaintext": syntax_def.name == "Plain Text",
+        })
+    }
 }
 
 #[cfg(test)]
@@ -239,11 +502,82 @@ mod tests {
             line_length_limit: None,
             extension: String::new(),
             theme: String::new(),
+            ..Default::default()
         };
         let result = determine_language(&query, &syntax_set);
         assert_eq!(result.unwrap().name, "TeX");
     }
 
+    #[test]
+    fn no_default_syntax() {
+        // The default syntax set has no grammar for these files, so they are
+        // plain text and have no scopes to map.
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        for (filepath, code) in [
+            ("Cargo.toml", "[package]\nname = \"sg\"\nversion = 1\n"),
+            ("config.ini", "; comment\n[section]\nkey=value\n"),
+            ("Dockerfile", "FROM ubuntu:22.04\nRUN apt-get update\n"),
+            ("scip.proto", "syntax = \"proto3\";\nmessage Document {}\n"),
+        ] {
+            let query = SourcegraphQuery {
+                filepath: filepath.to_string(),
+                code: code.to_string(),
+                ..Default::default()
+            };
+            let language = determine_language(&query, &syntax_set).unwrap();
+            assert_eq!(language.name, "Plain Text", "{}", filepath);
+        }
+    }
+
+    #[test]
+    fn language_info() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let query = |filepath: &str| SourcegraphQuery {
+            filepath: filepath.to_string(),
+            code: String::new(),
+            ..Default::default()
+        };
+
+        let info = detect_language_info(&query("main.go"), &syntax_set).unwrap();
+        assert_eq!(info.name, "Go");
+        assert_eq!(info.scope, "source.go");
+        assert!(info.file_extensions.contains(&"go".to_string()));
+        assert_eq!(detect_language_info(&query(""), &syntax_set), None);
+    }
+
+    #[test]
+    fn modelines() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let query = |code: &str, respect_modelines| SourcegraphQuery {
+            filepath: "script.py".to_string(),
+            code: code.to_string(),
+            respect_modelines,
+            ..Default::default()
+        };
+        let language = |code, respect_modelines| {
+            determine_language(&query(code, respect_modelines), &syntax_set)
+                .unwrap()
+                .name
+                .clone()
+        };
+
+        let ruby = "# vim: set ft=ruby:\nputs 'hi'\n";
+        assert_eq!(language(ruby, true), "Ruby");
+        assert_eq!(language(ruby, false), "Python");
+
+        let go = "package main\n\n\n\n\n\nfunc main() {}\n// vi: syntax=golang\n";
+        assert_eq!(language(go, true), "Go");
+
+        // Not modelines: the marker doesn't start a word, or sets no filetype.
+        assert_eq!(language("index: ft=ruby\n", true), "Python");
+        assert_eq!(language("# vim: set ts=4:\n", true), "Python");
+
+        assert_eq!(
+            modeline_filetype("/* vim: set sw=2 filetype=c : */"),
+            Some("c")
+        );
+    }
+
     #[test]
     fn cls_apex() {
         let syntax_set = SyntaxSet::load_defaults_newlines();
@@ -255,8 +589,155 @@ mod tests {
             line_length_limit: None,
             extension: String::new(),
             theme: String::new(),
+            ..Default::default()
         };
         let result = determine_language(&query, &syntax_set);
         assert_eq!(result.unwrap().name, "Apex");
     }
+
+    #[test]
+    fn filetype_case_and_aliases() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        for (filetype, name) in [
+            ("go", "Go"),
+            ("GO", "Go"),
+            (" Go\n", "Go"),
+            ("golang", "Go"),
+            ("c++", "C++"),
+            ("CPP", "C++"),
+        ] {
+            let query = SourcegraphQuery {
+                filepath: String::new(),
+                filetype: Some(filetype.to_string()),
+                code: String::new(),
+                ..Default::default()
+            };
+            let result = determine_language(&query, &syntax_set);
+            assert_eq!(result.unwrap().name, name, "{:?}", filetype);
+        }
+    }
+
+    #[test]
+    fn default_supported_filetypes() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let filetypes = supported_filetypes(&syntax_set);
+        assert!(filetypes.contains(&"go".to_string()));
+        assert!(filetypes.contains(&"python".to_string()));
+    }
+
+    #[test]
+    fn filepath_without_filetype() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let query = SourcegraphQuery {
+            filepath: "scripts/build.py".to_string(),
+            code: "print(1)\n".to_string(),
+            extension: "rb".to_string(),
+            ..Default::default()
+        };
+        let result = determine_language(&query, &syntax_set);
+        assert_eq!(result.unwrap().name, "Python");
+    }
+
+    #[test]
+    fn shebang_only() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let query = SourcegraphQuery {
+            filepath: String::new(),
+            code: "#!/usr/bin/env python\nprint(1)\n".to_string(),
+            ..Default::default()
+        };
+        let result = determine_language(&query, &syntax_set);
+        assert_eq!(result.unwrap().name, "Python");
+    }
+
+    #[test]
+    fn shebang_interpreters() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        for (shebang, name) in [
+            ("#!/bin/bash", "Bourne Again Shell (bash)"),
+            ("#!/bin/sh -e", "Bourne Again Shell (bash)"),
+            ("#!/usr/bin/env python3", "Python"),
+            ("#!/usr/bin/env -S node --harmony", "JavaScript"),
+            ("#!/usr/bin/ruby", "Ruby"),
+            ("#!/usr/bin/perl -w", "Perl"),
+        ] {
+            let query = SourcegraphQuery {
+                filepath: "bin/script".to_string(),
+                code: format!("{}\n", shebang),
+                ..Default::default()
+            };
+            let result = determine_language(&query, &syntax_set);
+            assert_eq!(result.unwrap().name, name, "{}", shebang);
+        }
+    }
+
+    #[test]
+    fn autodetect_snippets() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let python = "import os\n\ndef main():\n    print(os.getcwd())\n";
+        let shell = "cd /tmp\necho \"hello\" | grep h\n";
+
+        assert_eq!(detect_syntax(&syntax_set, python).name, "Python");
+        assert_eq!(
+            detect_syntax(&syntax_set, shell).name,
+            "Bourne Again Shell (bash)"
+        );
+        assert_eq!(
+            detect_syntax(&syntax_set, "hello world\n").name,
+            "Plain Text"
+        );
+
+        let document = highlight_autodetect(&syntax_set, python).unwrap();
+        assert!(!document.occurrences.is_empty());
+    }
+
+    #[test]
+    fn custom_syntax_set() {
+        let syntax_set = load_syntax_set(Some(Path::new("./testdata/syntaxes"))).unwrap();
+        let query = SourcegraphQuery {
+            filepath: "basket.fruit".to_string(),
+            code: "apple # and pear\n".to_string(),
+            ..Default::default()
+        };
+        let syntax_def = determine_language(&query, &syntax_set).unwrap();
+        assert_eq!(syntax_def.name, "Fruit");
+
+        let document =
+            DocumentGenerator::new(&syntax_set, syntax_def, &query.code, LimitConfig::default())
+                .generate()
+                .unwrap();
+        let kinds: Vec<_> = document.occurrences.iter().map(|o| o.syntax_kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                sg_lsif::SyntaxKind::IdentifierKeyword,
+                sg_lsif::SyntaxKind::Comment
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_syntax_set_filetype() {
+        let syntax_set = load_syntax_set(Some(Path::new("./testdata/syntaxes"))).unwrap();
+        let query = |filepath: &str| SourcegraphQuery {
+            filepath: filepath.to_string(),
+            code: "apple # and pear\n".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(determine_filetype(&query("basket.fruit")), "plain text");
+        assert_eq!(
+            determine_filetype_with_syntax_set(&query("basket.fruit"), &syntax_set),
+            "fruit"
+        );
+
+        // /lsif detects a missing filetype with the same syntaxes, "fruit"
+        // has no tree-sitter grammar though.
+        assert_eq!(
+            lsif_highlight_with_syntax_set(query("basket.fruit"), &syntax_set).unwrap_err(),
+            json!({"error": "fruit is not a valid filetype for treesitter"})
+        );
+        assert!(lsif_highlight_with_syntax_set(query("main.go"), &syntax_set).is_ok());
+        assert!(lsif_highlight_with_syntax_set(query(""), &syntax_set).is_err());
+    }
 }
@@ -0,0 +1,384 @@
+use std::path::Path;
+
+use sg_lsif::{Document, Occurrence};
+use syntect::{
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::{DocumentGenerator, HighlightError, LimitConfig};
+
+/// The kind of a line in a unified diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLineKind {
+    /// File headers (`diff --git`, `---`, `+++`, ...) and markers like
+    /// `\ No newline at end of file`.
+    Header,
+    /// Hunk headers (`@@ -1,3 +1,4 @@`).
+    Hunk,
+    /// Lines that are the same on both sides.
+    Context,
+    /// Lines starting with `+`.
+    Added,
+    /// Lines starting with `-`.
+    Removed,
+}
+
+/// The DiffDocumentGenerator highlights a unified diff.
+///
+/// Every line of the diff is tagged with a `DiffLineKind`. The code in the
+/// hunks (without the `+`, `-` or ` ` prefix) is highlighted in the language
+/// of the changed file, which is taken from the `+++` header (or the `---`
+/// header of deleted files) unless it is set with `with_payload_syntax`. The
+/// old side (context and removed lines) and the new side (context and added
+/// lines) are parsed separately, so each of them sees a consistent file.
+pub struct DiffDocumentGenerator<'a> {
+    syntax_set: &'a SyntaxSet,
+    code: &'a str,
+    payload_syntax: Option<&'a SyntaxReference>,
+}
+
+impl<'a> DiffDocumentGenerator<'a> {
+    pub fn new(ss: &'a SyntaxSet, code: &'a str) -> Self {
+        DiffDocumentGenerator {
+            syntax_set: ss,
+            code,
+            payload_syntax: None,
+        }
+    }
+
+    /// Highlights the code in every hunk with `sr`, instead of detecting the
+    /// language from the `+++` headers.
+    pub fn with_payload_syntax(mut self, sr: &'a SyntaxReference) -> Self {
+        self.payload_syntax = Some(sr);
+        self
+    }
+
+    /// Returns the document for the code in the hunks, and the kind of every
+    /// line of the diff.
+    pub fn generate(self) -> Result<(Document, Vec<DiffLineKind>), HighlightError> {
+        let mut document = Document::new();
+        let mut line_kinds = Vec::new();
+
+        // The generators for the old and the new side of the current file.
+        let mut sides: Option<(DocumentGenerator, DocumentGenerator)> = None;
+        let mut old_path = "";
+        let mut hunk = HunkState::default();
+
+        for (row, line) in LinesWithEndings::from(self.code).enumerate() {
+            // File headers are only looked for outside of hunks, since a
+            // removed `-- comment` line also starts with `--- `.
+            let kind = hunk
+                .next_line_kind(line)
+                .unwrap_or_else(|| file_line_kind(line));
+            line_kinds.push(kind);
+
+            let (old, new) = match kind {
+                DiffLineKind::Header => {
+                    if let Some(path) = line.strip_prefix("--- ") {
+                        old_path = header_path(path);
+                    } else if let Some(path) = line.strip_prefix("+++ ") {
+                        if let Some((mut old, mut new)) = sides.take() {
+                            old.finish()?;
+                            new.finish()?;
+                        }
+                        // Deleted files only have a name on the old side.
+                        let path = match header_path(path) {
+                            "/dev/null" => old_path,
+                            path => path,
+                        };
+                        let sr = self.payload_syntax(path);
+                        sides = Some((
+                            DocumentGenerator::new(self.syntax_set, sr, "", LimitConfig::default()),
+                            DocumentGenerator::new(self.syntax_set, sr, "", LimitConfig::default()),
+                        ));
+                    }
+                    continue;
+                }
+                DiffLineKind::Hunk => {
+                    hunk = HunkState::parse(line).unwrap_or_default();
+                    continue;
+                }
+                _ => match &mut sides {
+                    Some((old, new)) => (old, new),
+                    None => continue,
+                },
+            };
+
+            // Drop the prefix, but keep the empty context lines that some
+            // tools write without the leading space.
+            let payload = line.get(1..).unwrap_or("");
+            let occurrences = match kind {
+                DiffLineKind::Removed => process_line(old, row, payload)?,
+                DiffLineKind::Added => process_line(new, row, payload)?,
+                _ => {
+                    process_line(old, row, payload)?;
+                    process_line(new, row, payload)?
+                }
+            };
+            document.occurrences.extend(occurrences);
+        }
+
+        if let Some((mut old, mut new)) = sides {
+            old.finish()?;
+            new.finish()?;
+        }
+
+        Ok((document, line_kinds))
+    }
+
+    fn payload_syntax(&self, path: &str) -> &'a SyntaxReference {
+        if let Some(sr) = self.payload_syntax {
+            return sr;
+        }
+
+        let path = Path::new(path);
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| self.syntax_set.find_syntax_by_extension(name))
+            .or_else(|| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .and_then(|ext| self.syntax_set.find_syntax_by_extension(ext))
+            })
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text())
+    }
+}
+
+/// The number of lines left on each side of the current hunk, taken from
+/// the counts in its `@@ -a,b +c,d @@` header.
+#[derive(Default)]
+struct HunkState {
+    old: usize,
+    new: usize,
+}
+
+impl HunkState {
+    fn parse(line: &str) -> Option<HunkState> {
+        let mut ranges = line.strip_prefix("@@ ")?.split(' ');
+        let old = ranges.next()?.strip_prefix('-')?;
+        let new = ranges.next()?.strip_prefix('+')?;
+        Some(HunkState {
+            old: range_len(old)?,
+            new: range_len(new)?,
+        })
+    }
+
+    /// Returns the kind of `line` if it belongs to the hunk, and counts it.
+    /// Returns `None` once the hunk is over.
+    fn next_line_kind(&mut self, line: &str) -> Option<DiffLineKind> {
+        if line.starts_with('\\') {
+            return Some(DiffLineKind::Header);
+        }
+
+        let kind = if line.starts_with('+') && self.new > 0 {
+            self.new -= 1;
+            DiffLineKind::Added
+        } else if line.starts_with('-') && self.old > 0 {
+            self.old -= 1;
+            DiffLineKind::Removed
+        } else if (line.starts_with(' ') || line == "\n" || line == "\r\n")
+            && self.old > 0
+            && self.new > 0
+        {
+            self.old -= 1;
+            self.new -= 1;
+            DiffLineKind::Context
+        } else {
+            *self = HunkState::default();
+            return None;
+        };
+        Some(kind)
+    }
+}
+
+/// The number of lines in a `start,len` hunk range, where `len` defaults to 1.
+fn range_len(range: &str) -> Option<usize> {
+    match range.split_once(',') {
+        Some((_, len)) => len.parse().ok(),
+        None => Some(1),
+    }
+}
+
+/// The kind of a line outside of a hunk.
+fn file_line_kind(line: &str) -> DiffLineKind {
+    if line.starts_with("@@") {
+        DiffLineKind::Hunk
+    } else {
+        DiffLineKind::Header
+    }
+}
+
+/// The path in a `---` or `+++` header, without the timestamp that some
+/// tools append after a tab.
+fn header_path(header: &str) -> &str {
+    header.split('\t').next().unwrap_or(header).trim_end()
+}
+
+/// Highlights one line of a side of the diff, and moves the occurrences past
+/// the one column prefix of the diff line. The occurrences are all on one
+/// line, so their ranges have 3 elements.
+fn process_line(
+    generator: &mut DocumentGenerator,
+    row: usize,
+    payload: &str,
+) -> Result<Vec<Occurrence>, HighlightError> {
+    generator.resume_at(row)?;
+    let mut occurrences = generator.process_line(row, payload)?;
+    for occurrence in occurrences.iter_mut() {
+        occurrence.range[1] += 1;
+        occurrence.range[2] += 1;
+    }
+    Ok(occurrences)
+}
+
+#[cfg(test)]
+mod test {
+    use sg_lsif::SyntaxKind;
+
+    use super::*;
+
+    #[test]
+    fn test_tags_diff_lines() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let src = "diff --git a/main.go b/main.go
+--- a/main.go
++++ b/main.go
+@@ -1,3 +1,3 @@
+ package main
+-var x = 1
++var x = 2
+";
+        let (document, line_kinds) = DiffDocumentGenerator::new(&syntax_set, src)
+            .generate()
+            .unwrap();
+
+        assert_eq!(
+            line_kinds,
+            vec![
+                DiffLineKind::Header,
+                DiffLineKind::Header,
+                DiffLineKind::Header,
+                DiffLineKind::Hunk,
+                DiffLineKind::Context,
+                DiffLineKind::Removed,
+                DiffLineKind::Added,
+            ]
+        );
+
+        let numbers: Vec<_> = document
+            .occurrences
+            .iter()
+            .filter(|o| o.syntax_kind == SyntaxKind::NumericLiteral)
+            .map(|o| o.range.clone())
+            .collect();
+        assert_eq!(numbers, vec![vec![5, 9, 10], vec![6, 9, 10]]);
+    }
+
+    #[test]
+    fn test_hunk_lines_that_look_like_headers() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let src = "--- a/main.lua
++++ b/main.lua
+@@ -1,2 +1,2 @@
+--- old comment
++++ new comment
+ local x = 1
+";
+        let (document, line_kinds) = DiffDocumentGenerator::new(&syntax_set, src)
+            .generate()
+            .unwrap();
+
+        assert_eq!(
+            line_kinds,
+            vec![
+                DiffLineKind::Header,
+                DiffLineKind::Header,
+                DiffLineKind::Hunk,
+                DiffLineKind::Removed,
+                DiffLineKind::Added,
+                DiffLineKind::Context,
+            ]
+        );
+
+        // The old side is still Lua after the `--- old comment` line.
+        let comments: Vec<_> = document
+            .occurrences
+            .iter()
+            .filter(|o| o.syntax_kind == SyntaxKind::Comment)
+            .map(|o| o.range[0])
+            .collect();
+        assert_eq!(comments, vec![3]);
+        let numbers: Vec<_> = document
+            .occurrences
+            .iter()
+            .filter(|o| o.syntax_kind == SyntaxKind::NumericLiteral)
+            .map(|o| o.range.clone())
+            .collect();
+        assert_eq!(numbers, vec![vec![5, 11, 12]]);
+    }
+
+    #[test]
+    fn test_header_paths() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let numbers = |src| -> Vec<Vec<i32>> {
+            let (document, _) = DiffDocumentGenerator::new(&syntax_set, src)
+                .generate()
+                .unwrap();
+            document
+                .occurrences
+                .iter()
+                .filter(|o| o.syntax_kind == SyntaxKind::NumericLiteral)
+                .map(|o| o.range.clone())
+                .collect()
+        };
+
+        let with_timestamps = "--- a/main.go\t2022-01-01 10:00:00.000000000 +0100
++++ b/main.go\t2022-01-02 10:00:00.000000000 +0100
+@@ -1 +1 @@
+-var x = 1
++var x = 2
+";
+        assert_eq!(
+            numbers(with_timestamps),
+            vec![vec![3, 9, 10], vec![4, 9, 10]]
+        );
+
+        let deleted = "--- a/main.go
++++ /dev/null
+@@ -1,2 +0,0 @@
+-package main
+-var x = 1
+";
+        assert_eq!(numbers(deleted), vec![vec![4, 9, 10]]);
+    }
+
+    #[test]
+    fn test_payload_syntax() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let go = syntax_set.find_syntax_by_extension("go").unwrap();
+        let numbers = |generator: DiffDocumentGenerator| -> Vec<Vec<i32>> {
+            let (document, _) = generator.generate().unwrap();
+            document
+                .occurrences
+                .iter()
+                .filter(|o| o.syntax_kind == SyntaxKind::NumericLiteral)
+                .map(|o| o.range.clone())
+                .collect()
+        };
+
+        // The path has no extension, so the hunks are plain text unless the
+        // syntax is set.
+        let src = "--- a/script
++++ b/script
+@@ -1 +1 @@
+-var x = 1
++var x = 2
+";
+        assert!(numbers(DiffDocumentGenerator::new(&syntax_set, src)).is_empty());
+        assert_eq!(
+            numbers(DiffDocumentGenerator::new(&syntax_set, src).with_payload_syntax(go)),
+            vec![vec![3, 9, 10], vec![4, 9, 10]]
+        );
+    }
+}
@@ -252,6 +252,7 @@ mod tests {
             extension: String::new(),
             theme: String::new(),
             css: true,
+            ..Default::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -282,6 +283,7 @@ mod tests {
             extension: String::new(),
             theme: String::new(),
             css: true,
+            ..Default::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -306,6 +308,7 @@ mod tests {
             extension: String::new(),
             theme: String::new(),
             css: true,
+            ..Default::default()
         };
         let expected = "<table>\
                             <tbody>\
@@ -331,6 +334,7 @@ mod tests {
             extension: String::new(),
             theme: String::new(),
             css: true,
+            ..Default::default()
         };
         let expected = "<table>\
                             <tbody>\
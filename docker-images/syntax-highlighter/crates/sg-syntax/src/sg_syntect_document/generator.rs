@@ -0,0 +1,1811 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    iter::Enumerate,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use sg_lsif::{Document, Occurrence, SymbolRole, SyntaxKind};
+use syntect::{
+    highlighting::{Color, Highlighter, Theme},
+    parsing::{BasicScopeStackOp, ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+use super::{
+    highlight_manager::{new_occurrence, HighlightManager, PartialHighlight},
+    scope_mapping::lookup_scope,
+    HighlightError, LanguageError,
+};
+use crate::{determine_language, sg_treesitter::PackedRange, SourcegraphQuery};
+
+lazy_static::lazy_static! {
+    /// The scope of the names of things that are being defined, like
+    /// `entity.name.function` or `entity.name.type`.
+    static ref DEFINITION_SCOPE: Scope = Scope::new("entity.name").unwrap();
+
+    /// The scope that syntaxes use for code they consider a syntax error.
+    static ref INVALID_SCOPE: Scope = Scope::new("invalid.illegal").unwrap();
+}
+
+/// How the `character` of an occurrence range is counted within its line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnEncoding {
+    /// Columns are byte offsets into the UTF-8 encoded line.
+    Utf8,
+    /// Columns are UTF-16 code units, which is what LSP clients expect.
+    Utf16,
+    /// Columns are Unicode scalar values (Rust `char`s).
+    Utf32,
+    /// Like `Utf32`, but every tab advances to the next multiple of
+    /// `tab_width` columns (its tab stop), the way editors render them.
+    ExpandedTabs { tab_width: usize },
+    /// Columns are extended grapheme clusters, i.e. what a user perceives as
+    /// a single character. A ZWJ emoji sequence like 👩‍🔬 is one column,
+    /// even though it is made up of three `char`s. Positions inside of a
+    /// cluster snap to its start.
+    GraphemeCluster,
+}
+
+impl Default for ColumnEncoding {
+    fn default() -> Self {
+        ColumnEncoding::Utf32
+    }
+}
+
+impl ColumnEncoding {
+    /// Returns the number of columns that `text` spans in this encoding, when
+    /// it starts a line.
+    fn len(self, text: &str) -> usize {
+        self.advance(0, text)
+    }
+
+    /// Returns the column after `text`, when it starts at `column`. Only the
+    /// width of tabs depends on where they start.
+    fn advance(self, column: usize, text: &str) -> usize {
+        match self {
+            ColumnEncoding::Utf8 => column + text.len(),
+            ColumnEncoding::Utf16 => column + text.chars().map(char::len_utf16).sum::<usize>(),
+            ColumnEncoding::Utf32 => column + text.chars().count(),
+            ColumnEncoding::ExpandedTabs { tab_width } => {
+                let tab_width = tab_width.max(1);
+                text.chars().fold(column, |column, c| {
+                    if c == '\t' {
+                        column - column % tab_width + tab_width
+                    } else {
+                        column + 1
+                    }
+                })
+            }
+            ColumnEncoding::GraphemeCluster => column + text.graphemes(true).count(),
+        }
+    }
+}
+
+/// Scopes that span everything (like `source.go` or `text.html.basic`) are
+/// never highlighted, so they are not reported as unhandled. This includes
+/// the scopes of embedded languages, like `source.js.embedded.html`; the
+/// scopes inside of them are mapped like any other.
+///
+/// These are the defaults for `DocumentGenerator::with_ignore_scopes`.
+const IGNORED_SCOPES: &[&str] = &["source", "text"];
+
+/// Statistics about a single `DocumentGenerator::generate_with_stats` run.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateStats {
+    /// The number of lines that were parsed.
+    pub lines: usize,
+    /// The number of occurrences in the document.
+    pub occurrences: usize,
+    /// The number of distinct scopes without a `SyntaxKind`, see
+    /// `DocumentGenerator::generate_with_report`.
+    pub unhandled_scopes: usize,
+    /// The time spent parsing and generating the document.
+    pub duration: Duration,
+    /// Whether lines or occurrences were dropped because the document
+    /// reached one of its `LimitConfig` limits.
+    pub truncated: bool,
+    /// The rows of lines after which the open highlights no longer matched
+    /// syntect's scope stack, e.g. because a scope was popped that was never
+    /// pushed. These point at problems in the syntax definition.
+    pub suspicious_lines: Vec<usize>,
+    /// The lowest and highest nesting depth (see `DocumentGenerator::depth`)
+    /// on each parsed line, e.g. for deriving folding ranges. Only recorded
+    /// when enabled by `DocumentGenerator::with_depth_profile`.
+    pub depth_profile: Vec<(usize, usize)>,
+}
+
+/// Limits on the work done for a single document, to bound the time and
+/// memory spent on pathological files (minified, generated or huge files).
+///
+/// Every limit is disabled by default. Lines that are skipped because of
+/// `max_line_len` get no occurrences; the other limits stop the document
+/// early and set `GenerateStats::truncated`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitConfig {
+    /// Lines longer than this many bytes are not parsed.
+    pub max_line_len: Option<usize>,
+    /// Parsing stops before the first line that ends past this many bytes.
+    pub max_total_bytes: Option<usize>,
+    /// Parsing stops after this many lines.
+    pub max_lines: Option<usize>,
+    /// Occurrences past this many are dropped.
+    pub max_occurrences: Option<usize>,
+}
+
+impl LimitConfig {
+    /// Only limits the length of lines, like the `line_length_limit` of a
+    /// `SourcegraphQuery`.
+    pub fn max_line_len(max_line_len: Option<usize>) -> Self {
+        LimitConfig {
+            max_line_len,
+            ..Default::default()
+        }
+    }
+}
+
+/// HighlightEngine owns a `SyntaxSet`, so that it is only loaded once and
+/// shared by every query that is highlighted with the engine.
+pub struct HighlightEngine {
+    syntax_set: SyntaxSet,
+}
+
+impl HighlightEngine {
+    /// Creates an engine with syntect's default syntaxes.
+    pub fn new() -> Self {
+        Self::with_syntax_set(SyntaxSet::load_defaults_newlines())
+    }
+
+    pub fn with_syntax_set(syntax_set: SyntaxSet) -> Self {
+        HighlightEngine { syntax_set }
+    }
+
+    pub fn syntax_set(&self) -> &SyntaxSet {
+        &self.syntax_set
+    }
+
+    /// Generates the `Document` for the code of `query`, see
+    /// `DocumentGenerator::for_query`.
+    pub fn highlight(&self, query: &SourcegraphQuery) -> Result<Document, HighlightError> {
+        DocumentGenerator::for_query(&self.syntax_set, query)?.generate()
+    }
+}
+
+impl Default for HighlightEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The DocumentGenerator generates an LSIF typed `Document` from the scopes
+/// that syntect produces while parsing `code`.
+///
+/// Each syntect scope that has a corresponding `SyntaxKind` in the scope
+/// mapping (see `default_scope_mapping`) becomes one or more occurrences in
+/// the document. Nested scopes are split by the `HighlightManager`, so the
+/// resulting occurrences never overlap.
+pub struct DocumentGenerator<'a> {
+    syntax_set: &'a SyntaxSet,
+    parse_state: ParseState,
+    // The state before the first line, which `reset` goes back to. Lines
+    // before its row are skipped.
+    initial_state: Checkpoint,
+    plain_text: bool,
+    stack: ScopeStack,
+    code: &'a str,
+    limits: LimitConfig,
+    column_encoding: ColumnEncoding,
+    line_range: Option<Range<usize>>,
+    scope_mapping: HashMap<String, SyntaxKind>,
+    ignore_scopes: HashSet<String>,
+    enabled_kinds: Option<HashSet<SyntaxKind>>,
+
+    // Resolving a scope takes the global SCOPE_REPO lock, so each scope is
+    // only resolved once per generator. The flag is set for scopes that are
+    // reported as unhandled.
+    pub(super) scope_kinds: HashMap<Scope, (Option<SyntaxKind>, bool)>,
+
+    pub(super) highlight_manager: HighlightManager,
+    // Kept as scopes rather than strings for the same reason, see
+    // `unhandled_scope_names`.
+    unhandled_scopes: HashSet<Scope>,
+    pub(super) suspicious_lines: Vec<usize>,
+    depth_profile: Option<Vec<(usize, usize)>>,
+
+    local_symbols: bool,
+    next_local_symbol: usize,
+    diagnostics: bool,
+    coalescing: bool,
+    outline: bool,
+
+    // Indentation is only significant in Python, so indent guides are only
+    // emitted for it.
+    python: bool,
+    indent_guides: bool,
+
+    // Syntect's Markdown syntax doesn't embed the language of fenced code
+    // blocks, so their contents are highlighted by a nested generator.
+    markdown: bool,
+    fence: Option<Fence<'a>>,
+
+    // Only set by `generate_with_colors`. The styles are the foreground
+    // colors of the scope stack after each op of the current line.
+    highlighter: Option<Highlighter<'a>>,
+    line_styles: Vec<(i32, Color)>,
+    colors: Vec<(usize, String)>,
+
+    // Only recorded by `generate_ref`, for `reparse`.
+    line_states: Option<Vec<LineState<'a>>>,
+    // Only recorded when enabled by `with_checkpoints`.
+    checkpoints: Option<Vec<Checkpoint>>,
+}
+
+/// The state of a `DocumentGenerator` before a row, from which generation
+/// can resume (see `DocumentGenerator::resume`).
+#[derive(Clone)]
+pub struct Checkpoint {
+    row: usize,
+    parse_state: ParseState,
+    stack: ScopeStack,
+    // The highlights of scopes that are still open, like a block comment.
+    highlights: Vec<PartialHighlight>,
+    next_local_symbol: usize,
+}
+
+impl Checkpoint {
+    fn new(generator: &DocumentGenerator, row: usize) -> Self {
+        Checkpoint {
+            row,
+            parse_state: generator.parse_state.clone(),
+            stack: generator.stack.clone(),
+            highlights: generator.highlight_manager.highlights.clone(),
+            next_local_symbol: generator.next_local_symbol,
+        }
+    }
+
+    /// The row that generation resumes at.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn parse_state(&self) -> &ParseState {
+        &self.parse_state
+    }
+
+    pub fn scope_stack(&self) -> &ScopeStack {
+        &self.stack
+    }
+
+    /// Returns whether `generator` is in this state, regardless of the row.
+    fn matches(&self, generator: &DocumentGenerator) -> bool {
+        self.parse_state == generator.parse_state
+            && self.stack == generator.stack
+            && self.highlights == generator.highlight_manager.highlights
+            && self.next_local_symbol == generator.next_local_symbol
+    }
+}
+
+/// A line of the code and the state of the generator before it.
+struct LineState<'a> {
+    line: &'a str,
+    state: Checkpoint,
+}
+
+/// A fenced code block in a Markdown file.
+struct Fence<'a> {
+    /// The run of backticks or tildes that opened the block, which must also
+    /// close it.
+    marker: String,
+    /// The generator for the contents, or None if the language is unknown.
+    generator: Option<Box<DocumentGenerator<'a>>>,
+}
+
+impl<'a> DocumentGenerator<'a> {
+    pub fn new(
+        ss: &'a SyntaxSet,
+        sr: &SyntaxReference,
+        code: &'a str,
+        limits: LimitConfig,
+    ) -> Self {
+        DocumentGenerator {
+            code,
+            syntax_set: ss,
+            parse_state: ParseState::new(sr),
+            initial_state: Checkpoint {
+                row: 0,
+                parse_state: ParseState::new(sr),
+                stack: ScopeStack::new(),
+                highlights: Vec::new(),
+                next_local_symbol: 0,
+            },
+            plain_text: sr.name == "Plain Text",
+            stack: ScopeStack::new(),
+            limits,
+            column_encoding: ColumnEncoding::default(),
+            line_range: None,
+            scope_mapping: Self::default_scope_mapping(),
+            ignore_scopes: IGNORED_SCOPES.iter().map(|s| s.to_string()).collect(),
+            enabled_kinds: None,
+            scope_kinds: HashMap::new(),
+            highlight_manager: HighlightManager::default(),
+            unhandled_scopes: HashSet::new(),
+            suspicious_lines: Vec::new(),
+            depth_profile: None,
+            local_symbols: false,
+            next_local_symbol: 0,
+            diagnostics: false,
+            coalescing: false,
+            outline: false,
+            python: sr.name == "Python",
+            indent_guides: false,
+            markdown: sr.name == "Markdown",
+            fence: None,
+            highlighter: None,
+            line_styles: Vec::new(),
+            colors: Vec::new(),
+            line_states: None,
+            checkpoints: None,
+        }
+    }
+
+    /// Creates a generator that resumes from `checkpoint`, which another
+    /// generator for the same syntax and code recorded (see
+    /// `with_checkpoints`). `code` is the whole file: the lines before the
+    /// checkpoint's row are skipped, so the document only has occurrences for
+    /// the rest of the file, with the same positions as a full generation.
+    ///
+    /// Markdown checkpoints don't include the state of fenced code blocks, so
+    /// resuming inside of one highlights the rest of the block as Markdown.
+    pub fn resume(
+        ss: &'a SyntaxSet,
+        sr: &SyntaxReference,
+        code: &'a str,
+        limits: LimitConfig,
+        checkpoint: &Checkpoint,
+    ) -> Self {
+        let mut generator = DocumentGenerator::new(ss, sr, code, limits);
+        generator.initial_state = checkpoint.clone();
+        generator.reset(code);
+        generator
+    }
+
+    /// Creates a generator for the code of `query`, using the syntax that
+    /// `determine_language` picks for it and the query's line length limit.
+    ///
+    /// If no language matches, this uses the plain text syntax when the
+    /// query sets `fallback_plain_text`, and returns an error otherwise.
+    pub fn for_query(
+        ss: &'a SyntaxSet,
+        query: &'a SourcegraphQuery,
+    ) -> Result<Self, LanguageError> {
+        let sr = match determine_language(query, ss) {
+            Ok(sr) => sr,
+            Err(_) if query.fallback_plain_text => ss.find_syntax_plain_text(),
+            Err(_) => {
+                return Err(LanguageError::NoMatchingLanguage {
+                    filepath: query.filepath.clone(),
+                    extension: query.extension.clone(),
+                })
+            }
+        };
+
+        Ok(Self::new(
+            ss,
+            sr,
+            &query.code,
+            LimitConfig::max_line_len(query.line_length_limit),
+        ))
+    }
+
+    /// The mapping from syntect scopes to `SyntaxKind`s that is used unless
+    /// `with_scope_mapping` is called.
+    pub fn default_scope_mapping() -> HashMap<String, SyntaxKind> {
+        [
+            ("comment", SyntaxKind::Comment),
+            // Most syntaxes use a plain constant.language for true, false and
+            // nil, but some do say which one it is.
+            ("constant.language", SyntaxKind::IdentifierBuiltin),
+            ("constant.language.boolean", SyntaxKind::BooleanLiteral),
+            ("constant.language.null", SyntaxKind::IdentifierNull),
+            ("constant.character.escape", SyntaxKind::StringLiteralEscape),
+            (
+                "constant.character.escape.backslash.regexp",
+                SyntaxKind::RegexEscape,
+            ),
+            ("constant.numeric", SyntaxKind::NumericLiteral),
+            // Named constants, like `FOO` in Rust's `const FOO: u32 = 1;`.
+            ("constant.other", SyntaxKind::IdentifierConstant),
+            (
+                "constant.other.character-class.escape",
+                SyntaxKind::RegexEscape,
+            ),
+            ("entity.name.class", SyntaxKind::IdentifierType),
+            ("entity.name.enum", SyntaxKind::IdentifierType),
+            ("entity.name.function", SyntaxKind::IdentifierFunction),
+            ("entity.name.interface", SyntaxKind::IdentifierType),
+            // Labels, like `outer:` in Go. SCIP has no kind for them, so they
+            // are plain names. Like other entity.name scopes, they are
+            // definitions.
+            ("entity.name.label", SyntaxKind::Identifier),
+            // Namespaces and packages, like `std` in C++ or `Foo\Bar` in PHP.
+            ("entity.name.namespace", SyntaxKind::IdentifierModule),
+            // YAML anchors (`&foo`) and aliases (`*foo`) name a node, so
+            // they are constants. The `&` and `*` get the same kind as the
+            // name.
+            ("entity.name.other.anchor", SyntaxKind::IdentifierConstant),
+            ("entity.name.struct", SyntaxKind::IdentifierType),
+            ("entity.name.trait", SyntaxKind::IdentifierType),
+            ("entity.name.type", SyntaxKind::IdentifierType),
+            ("entity.name.union", SyntaxKind::IdentifierType),
+            ("keyword", SyntaxKind::IdentifierKeyword),
+            ("keyword.control.flow.alias", SyntaxKind::IdentifierConstant),
+            (
+                "keyword.control.property.anchor",
+                SyntaxKind::IdentifierConstant,
+            ),
+            // Preprocessor directives, like `#include` or `#ifdef`. Other
+            // languages use keyword.control.import for `import`, so the
+            // directives without a more specific scope are only mapped for
+            // C and C++.
+            ("keyword.control.import.c", SyntaxKind::IdentifierMacro),
+            ("keyword.control.import.c++", SyntaxKind::IdentifierMacro),
+            ("keyword.control.import.define", SyntaxKind::IdentifierMacro),
+            ("keyword.control.import.error", SyntaxKind::IdentifierMacro),
+            (
+                "keyword.control.import.include",
+                SyntaxKind::IdentifierMacro,
+            ),
+            ("keyword.control.preprocessor", SyntaxKind::IdentifierMacro),
+            ("keyword.other.preprocessor", SyntaxKind::IdentifierMacro),
+            ("keyword.operator", SyntaxKind::IdentifierOperator),
+            // The keys of Java properties files (`key=value`), which the
+            // syntax scopes as keywords. The values have no scope.
+            ("keyword.other.java-props", SyntaxKind::IdentifierAttribute),
+            // SCIP has no kinds for prose, so Markdown borrows kinds that
+            // themes render distinctly: headings are tags (like `<h1>` in
+            // HTML), strong emphasis is a keyword, emphasis is an attribute
+            // and link targets are special strings. The markers, like `#` or
+            // `**`, get the same kind as the text they mark.
+            ("markup.bold", SyntaxKind::IdentifierKeyword),
+            ("markup.heading", SyntaxKind::Tag),
+            ("markup.italic", SyntaxKind::IdentifierAttribute),
+            ("markup.underline.link", SyntaxKind::StringLiteralSpecial),
+            // Regex literals (string.regexp) are strings, but the operators
+            // inside of them have their own kinds.
+            ("keyword.operator.or.regexp", SyntaxKind::RegexJoin),
+            (
+                "keyword.operator.quantifier.regexp",
+                SyntaxKind::RegexRepeated,
+            ),
+            // The keys of objects in JSON and similar formats. The strings
+            // inside of them are part of the key, see `nested_kind`.
+            ("meta.mapping.key", SyntaxKind::IdentifierAttribute),
+            (
+                "meta.structure.dictionary.key",
+                SyntaxKind::IdentifierAttribute,
+            ),
+            ("punctuation", SyntaxKind::PunctuationBracket),
+            ("punctuation.accessor", SyntaxKind::PunctuationDelimiter),
+            (
+                "punctuation.definition.alias",
+                SyntaxKind::IdentifierConstant,
+            ),
+            (
+                "punctuation.definition.anchor",
+                SyntaxKind::IdentifierConstant,
+            ),
+            // The `@` or `#` that starts a decorator, annotation or attribute.
+            (
+                "punctuation.definition.annotation",
+                SyntaxKind::IdentifierAttribute,
+            ),
+            ("punctuation.definition.bold", SyntaxKind::IdentifierKeyword),
+            ("punctuation.definition.heading", SyntaxKind::Tag),
+            (
+                "punctuation.definition.italic",
+                SyntaxKind::IdentifierAttribute,
+            ),
+            (
+                "punctuation.definition.preprocessor",
+                SyntaxKind::IdentifierMacro,
+            ),
+            // The quotes are part of the string.
+            ("punctuation.definition.string", SyntaxKind::StringLiteral),
+            ("punctuation.separator", SyntaxKind::PunctuationDelimiter),
+            ("punctuation.terminator", SyntaxKind::PunctuationDelimiter),
+            // Column constraints in SQL, like `PRIMARY KEY` or `NOT NULL`.
+            ("storage.modifier.sql", SyntaxKind::IdentifierKeyword),
+            // storage.type is used both for declaration keywords (`func`,
+            // `struct`, `class`) and for primitive types (`int`), which are
+            // all keywords of the language.
+            ("storage.type", SyntaxKind::IdentifierKeyword),
+            // The prefix of a Python string, like the `f` of an f-string, is
+            // part of the string.
+            ("storage.type.string.python", SyntaxKind::StringLiteral),
+            // YAML tags, like `!!str`, which give the type of a node.
+            ("storage.type.tag-handle", SyntaxKind::IdentifierType),
+            ("string", SyntaxKind::StringLiteral),
+            ("support.function", SyntaxKind::IdentifierBuiltin),
+            ("support.other.namespace", SyntaxKind::IdentifierModule),
+            ("support.type", SyntaxKind::IdentifierBuiltinType),
+            ("variable", SyntaxKind::Identifier),
+            ("variable.annotation", SyntaxKind::IdentifierAttribute),
+            ("variable.other.alias", SyntaxKind::IdentifierConstant),
+            // Type parameters, like `T` in Java's `class Box<T>`. SCIP has no
+            // kind for them, and they are used as types.
+            ("variable.parameter.type", SyntaxKind::IdentifierType),
+            ("variable.other.constant", SyntaxKind::IdentifierConstant),
+            ("variable.function", SyntaxKind::IdentifierFunction),
+        ]
+        .into_iter()
+        .map(|(scope, kind)| (scope.to_string(), kind))
+        .collect()
+    }
+
+    /// Replaces the mapping from syntect scopes to `SyntaxKind`s. Keys are
+    /// scope prefixes like `keyword` or `keyword.operator`; the most specific
+    /// matching prefix wins. See `load_scope_mapping` to load one from a
+    /// file.
+    pub fn with_scope_mapping(mut self, scope_mapping: HashMap<String, SyntaxKind>) -> Self {
+        self.scope_mapping = scope_mapping;
+        self.scope_kinds.clear();
+        self
+    }
+
+    /// Replaces the top-level scopes that are never highlighted. A scope is
+    /// ignored when its first atom is in `ignore_scopes`, so `source` ignores
+    /// `source.go` as well as `source.js.embedded.html`. Ignored scopes are
+    /// not reported as unhandled either. Defaults to `source` and `text`.
+    pub fn with_ignore_scopes(mut self, ignore_scopes: HashSet<String>) -> Self {
+        self.ignore_scopes = ignore_scopes;
+        self.scope_kinds.clear();
+        self
+    }
+
+    /// Only emits occurrences of the given kinds. Scopes of other kinds are
+    /// still parsed, but are treated like unmapped scopes, so they don't
+    /// split the highlight they are nested in.
+    pub fn with_enabled_kinds(mut self, enabled_kinds: HashSet<SyntaxKind>) -> Self {
+        self.enabled_kinds = Some(enabled_kinds);
+        self.scope_kinds.clear();
+        self
+    }
+
+    /// Sets how the `character` of occurrence ranges is counted. Defaults to
+    /// `ColumnEncoding::Utf32`.
+    pub fn with_column_encoding(mut self, column_encoding: ColumnEncoding) -> Self {
+        self.column_encoding = column_encoding;
+        self
+    }
+
+    /// Expands tabs to tab stops every `tab_width` columns. This is a
+    /// shorthand for `with_column_encoding(ColumnEncoding::ExpandedTabs {
+    /// tab_width })`; by default a tab is a single column.
+    pub fn with_tab_width(self, tab_width: usize) -> Self {
+        self.with_column_encoding(ColumnEncoding::ExpandedTabs { tab_width })
+    }
+
+    /// Only emits occurrences for the rows in `[start, end)`. The code is
+    /// still parsed from the first line, so that the scopes in the range are
+    /// correct, but parsing stops after the range.
+    pub fn with_line_range(mut self, start: usize, end: usize) -> Self {
+        self.line_range = Some(start..end);
+        self
+    }
+
+    /// When enabled, every definition (an `entity.name` scope, like the name
+    /// of a function or type) gets its own `local N` symbol, so there is
+    /// something to navigate to.
+    pub fn with_local_symbols(mut self, local_symbols: bool) -> Self {
+        self.local_symbols = local_symbols;
+        self
+    }
+
+    /// When enabled, only the names of definitions (the `entity.name` scopes,
+    /// like functions, types and constants) get occurrences, which is enough
+    /// for a symbol outline. Everything else, like strings, punctuation and
+    /// keywords, is treated like unmapped scopes.
+    pub fn with_outline_mode(mut self, outline: bool) -> Self {
+        self.outline = outline;
+        self.scope_kinds.clear();
+        self
+    }
+
+    /// When enabled, `generate_with_stats` reports the lowest and highest
+    /// nesting depth on each line in `GenerateStats::depth_profile`.
+    pub fn with_depth_profile(mut self, depth_profile: bool) -> Self {
+        self.depth_profile = if depth_profile {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Returns the number of scopes that are open after the lines parsed so
+    /// far. This counts every scope, including the ones without a kind (like
+    /// the `meta.block` of a pair of braces) and the scope of the whole file,
+    /// so it tracks how deeply the code is nested.
+    pub fn depth(&self) -> usize {
+        self.highlight_manager.depth()
+    }
+
+    /// When enabled, code that the syntax marks as `invalid.illegal` (like a
+    /// stray closing bracket) gets an occurrence with a warning diagnostic.
+    /// Such occurrences have no `SyntaxKind`, unless the scope mapping has
+    /// one for them.
+    pub fn with_diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self.scope_kinds.clear();
+        self
+    }
+
+    /// When enabled, adjacent occurrences of the same kind on a line (like
+    /// the `()` of a call) are merged into a single occurrence.
+    pub fn with_coalescing(mut self, coalescing: bool) -> Self {
+        self.coalescing = coalescing;
+        self
+    }
+
+    /// When enabled, the indentation at the start of each line of a Python
+    /// file gets an occurrence, e.g. for drawing indent guides. SCIP has no
+    /// kind for indentation, so these occurrences are `UnspecifiedSyntaxKind`;
+    /// they are the only ones of that kind that start at character 0. Blank
+    /// lines and lines inside of multi-line strings get none. Other languages
+    /// are not affected.
+    pub fn with_indent_guides(mut self, indent_guides: bool) -> Self {
+        self.indent_guides = indent_guides;
+        self
+    }
+
+    /// When enabled, scopes that don't cover any characters (that end right
+    /// where they start) are emitted as single positions, using the 3
+    /// element `[line, character, character]` range. Consumers can use them
+    /// as markers; by default they are dropped. Highlights that are only
+    /// empty where they are split, like a string in front of a nested
+    /// prefix, are not empty scopes.
+    pub fn with_emit_empty_ranges(mut self, emit_empty_ranges: bool) -> Self {
+        self.highlight_manager.emit_empty_ranges = emit_empty_ranges;
+        self
+    }
+
+    /// When enabled, the generator records a checkpoint after each line, from
+    /// which generation can resume at the next row (see `checkpoints` and
+    /// `resume`). Each checkpoint holds a copy of the parse state, so this
+    /// takes a lot more memory than the document itself.
+    pub fn with_checkpoints(mut self, checkpoints: bool) -> Self {
+        self.checkpoints = if checkpoints { Some(Vec::new()) } else { None };
+        self
+    }
+
+    /// Returns the checkpoints recorded by the last generation, or nothing if
+    /// they are disabled. The checkpoint at index `i` resumes at row `i + 1`
+    /// (or after the checkpoint the generator was resumed from).
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        self.checkpoints.as_deref().unwrap_or(&[])
+    }
+
+    /// Caps the number of occurrences in the document, to bound the memory
+    /// used for pathological files. Once the cap is reached, further
+    /// occurrences are dropped and `GenerateStats::truncated` is set. This is
+    /// a shorthand for setting `LimitConfig::max_occurrences`.
+    pub fn with_max_occurrences(mut self, max_occurrences: usize) -> Self {
+        self.limits.max_occurrences = Some(max_occurrences);
+        self
+    }
+
+    // generate takes ownership of self so that it can't be re-used, see
+    // generate_ref for a generator that can
+    pub fn generate(self) -> Result<Document, HighlightError> {
+        self.generate_with_report().map(|(document, _)| document)
+    }
+
+    /// Like `generate`, but the occurrences are explicitly sorted by their
+    /// range, which SCIP consumers assume. `generate` emits them in the order
+    /// the highlights are closed, which is not guaranteed to be sorted.
+    pub fn generate_sorted(self) -> Result<Document, HighlightError> {
+        let mut document = self.generate()?;
+        let mut occurrences = document.occurrences.into_vec();
+        sort_occurrences(&mut occurrences);
+        document.occurrences = occurrences.into();
+        Ok(document)
+    }
+
+    /// Like `generate_sorted`, but also returns which `PunctuationBracket`
+    /// occurrences match each other, e.g. for bracket pair colorization. The
+    /// map goes from the index of an opening bracket in the document's
+    /// occurrences to the index of its closing bracket. Unmatched brackets
+    /// are left out.
+    pub fn generate_with_bracket_pairs(
+        self,
+    ) -> Result<(Document, HashMap<usize, usize>), HighlightError> {
+        let code = self.code;
+        let column_encoding = self.column_encoding;
+        let document = self.generate_sorted()?;
+
+        let lines: Vec<&str> = code.lines().collect();
+        let mut open_brackets: Vec<(char, usize)> = Vec::new();
+        let mut pairs = HashMap::new();
+        for (idx, occurrence) in document.occurrences.iter().enumerate() {
+            if occurrence.syntax_kind != SyntaxKind::PunctuationBracket {
+                continue;
+            }
+
+            let bracket = lines
+                .get(occurrence.range[0] as usize)
+                .and_then(|line| char_at_column(line, column_encoding, occurrence.range[1]));
+            let open = match bracket {
+                Some(c @ ('(' | '[' | '{')) => {
+                    open_brackets.push((c, idx));
+                    continue;
+                }
+                Some(')') => '(',
+                Some(']') => '[',
+                Some('}') => '{',
+                _ => continue,
+            };
+            if let Some(&(c, open_idx)) = open_brackets.last() {
+                if c == open {
+                    open_brackets.pop();
+                    pairs.insert(open_idx, idx);
+                }
+            }
+        }
+
+        Ok((document, pairs))
+    }
+
+    /// Like `generate`, but also returns the sorted list of scopes in the file
+    /// that had no `SyntaxKind` in the scope mapping. This is useful when
+    /// extending the mapping for a new language.
+    pub fn generate_with_report(mut self) -> Result<(Document, Vec<String>), HighlightError> {
+        let (document, _) = self.generate_document()?;
+        Ok((document, self.unhandled_scope_names()))
+    }
+
+    fn unhandled_scope_names(&self) -> Vec<String> {
+        self.unhandled_scopes
+            .iter()
+            .map(|scope| scope.build_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Like `generate`, but leaves the generator usable. Every call starts
+    /// over from the first line of the code, so after a `reset` the same
+    /// generator can highlight another file.
+    ///
+    /// This also records the state before each line, so that `reparse` can
+    /// re-highlight the code after an edit.
+    pub fn generate_ref(&mut self) -> Result<Document, HighlightError> {
+        self.reset(self.code);
+        self.line_states = Some(Vec::new());
+        let (document, _) = self.generate_document()?;
+        Ok(document)
+    }
+
+    /// Re-highlights the code after the line at `edited_line` was replaced by
+    /// `new_line_text`, given the `old_document` from before the edit. The new
+    /// text must be a single line, including its line ending.
+    ///
+    /// Parsing resumes from the state before the edited line, as recorded by
+    /// `generate_ref`, and stops at the first line after it that starts in
+    /// the same state as before the edit: from there on, the old occurrences
+    /// are still correct. The limits and line range are not applied. Markdown
+    /// files are always reparsed from the first line, since the state of
+    /// their fenced code blocks is not recorded.
+    ///
+    /// Panics if the edited line wasn't parsed by `generate_ref` or a
+    /// previous `reparse`.
+    pub fn reparse(
+        &mut self,
+        old_document: &Document,
+        edited_line: usize,
+        new_line_text: &'a str,
+    ) -> Result<Document, HighlightError> {
+        let mut line_states = self
+            .line_states
+            .take()
+            .expect("reparse requires a generate_ref first");
+        assert!(
+            edited_line < line_states.len(),
+            "line {} was not parsed",
+            edited_line
+        );
+        line_states[edited_line].line = new_line_text;
+
+        let start = if self.markdown { 0 } else { edited_line };
+        self.restore(&line_states[start].state.clone());
+
+        let mut occurrences = Vec::new();
+        let mut resume = line_states.len();
+        let mut result = Ok(());
+        for (row, line_state) in line_states.iter_mut().enumerate().skip(start) {
+            if row > edited_line && line_state.state.matches(self) {
+                resume = row;
+                break;
+            }
+
+            line_state.state = Checkpoint::new(self, row);
+            let mut line_occurrences = match self.process_line(row, line_state.line) {
+                Ok(line_occurrences) => line_occurrences,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+            if self.coalescing {
+                coalesce_occurrences(&mut line_occurrences);
+            }
+            occurrences.extend(line_occurrences);
+        }
+        self.line_states = Some(line_states);
+        result?;
+
+        let row = |occurrence: &&Occurrence| occurrence.range[0] as usize;
+        let mut document = old_document.clone();
+        document.occurrences = old_document
+            .occurrences
+            .iter()
+            .filter(|occurrence| row(occurrence) < start)
+            .cloned()
+            .chain(occurrences)
+            .chain(
+                old_document
+                    .occurrences
+                    .iter()
+                    .filter(|occurrence| row(occurrence) >= resume)
+                    .cloned(),
+            )
+            .collect();
+        Ok(document)
+    }
+
+    /// Prepares the generator to highlight `code`, keeping its syntax and
+    /// configuration. A pooled server can reuse a generator this way instead
+    /// of creating one per file, which also keeps the resolved scope kinds.
+    pub fn reset(&mut self, code: &'a str) {
+        self.code = code;
+        self.restore(&self.initial_state.clone());
+        self.unhandled_scopes.clear();
+        self.suspicious_lines.clear();
+        if let Some(depth_profile) = &mut self.depth_profile {
+            depth_profile.clear();
+        }
+        self.line_styles.clear();
+        self.colors.clear();
+        self.line_states = None;
+        if let Some(checkpoints) = &mut self.checkpoints {
+            checkpoints.clear();
+        }
+    }
+
+    fn restore(&mut self, checkpoint: &Checkpoint) {
+        self.parse_state = checkpoint.parse_state.clone();
+        self.stack = checkpoint.stack.clone();
+        self.highlight_manager.highlights = checkpoint.highlights.clone();
+        self.next_local_symbol = checkpoint.next_local_symbol;
+        self.fence = None;
+    }
+
+    /// Like `generate`, but also returns statistics about the run, e.g. for
+    /// metrics.
+    pub fn generate_with_stats(mut self) -> Result<(Document, GenerateStats), HighlightError> {
+        let start = Instant::now();
+        let (document, mut stats) = self.generate_document()?;
+        stats.occurrences = document.occurrences.len();
+        stats.unhandled_scopes = self.unhandled_scopes.len();
+        stats.suspicious_lines = std::mem::take(&mut self.suspicious_lines);
+        stats.depth_profile = self.depth_profile.take().unwrap_or_default();
+        stats.duration = start.elapsed();
+        Ok((document, stats))
+    }
+
+    /// Returns the range and the full scope string (like `keyword.control.go`)
+    /// of every scope that syntect pushes, in the order in which the scopes
+    /// end. This is meant for writing scope mappings, so the scope mapping
+    /// is not used at all.
+    ///
+    /// Unlike occurrences, the ranges cover whole scopes, so they can span
+    /// lines and overlap. Scopes that are still open at the end of the code
+    /// (like `source.go`) end there.
+    pub fn generate_scope_dump(mut self) -> Result<Vec<(Vec<i32>, String)>, HighlightError> {
+        let range = |start: (i32, i32), end: (i32, i32)| {
+            if start.0 == end.0 {
+                vec![start.0, start.1, end.1]
+            } else {
+                vec![start.0, start.1, end.0, end.1]
+            }
+        };
+
+        let mut scopes = Vec::new();
+        let mut open: Vec<(Scope, (i32, i32))> = Vec::new();
+        let mut end = (0, 0);
+        for (row, line_contents) in LinesWithEndings::from(self.code).enumerate() {
+            let row = self.checked_row(row, line_contents)?;
+            let mut columns = LineColumns::new(line_contents, self.column_encoding);
+            let ops = self.parse_state.parse_line(line_contents, self.syntax_set);
+            for &(byte_offset, ref op) in ops.as_slice() {
+                let position = (row, columns.column(byte_offset));
+                self.stack
+                    .apply_with_hook(op, |basic_op, _| match basic_op {
+                        BasicScopeStackOp::Push(scope) => open.push((scope, position)),
+                        BasicScopeStackOp::Pop => {
+                            if let Some((scope, start)) = open.pop() {
+                                scopes.push((range(start, position), scope.build_string()));
+                            }
+                        }
+                    });
+            }
+            end = (row, columns.end_of_line);
+        }
+
+        while let Some((scope, start)) = open.pop() {
+            scopes.push((range(start, end), scope.build_string()));
+        }
+        Ok(scopes)
+    }
+
+    /// Like `generate`, but also returns the `(start, end)` byte offsets of
+    /// each occurrence in the code, in the same order as the occurrences.
+    /// `&code[start..end]` is the text of the occurrence.
+    pub fn generate_with_byte_offsets(
+        self,
+    ) -> Result<(Document, Vec<(usize, usize)>), HighlightError> {
+        let code = self.code;
+        let column_encoding = self.column_encoding;
+        let document = self.generate()?;
+
+        // The byte offset at which each line starts, and its contents.
+        let mut line_start = 0;
+        let lines: Vec<(usize, &str)> = LinesWithEndings::from(code)
+            .map(|line| {
+                let start = line_start;
+                line_start += line.len();
+                (start, trim_line_ending(line))
+            })
+            .collect();
+        let byte_offset = |row: i32, col: i32| match lines.get(row as usize) {
+            Some((start, line)) => {
+                start + column_byte_offset(line, column_encoding, col).unwrap_or(line.len())
+            }
+            None => code.len(),
+        };
+
+        let offsets = document
+            .occurrences
+            .iter()
+            .map(|occurrence| {
+                let range = PackedRange::from_vec(&occurrence.range);
+                (
+                    byte_offset(range.start_line, range.start_col),
+                    byte_offset(range.end_line, range.end_col),
+                )
+            })
+            .collect();
+        Ok((document, offsets))
+    }
+
+    /// Splits the code into runs of text with the kind of their occurrence,
+    /// or None for the text between occurrences, so that the runs add up to
+    /// the whole code. Adjacent runs of the same kind are merged. Occurrences
+    /// without a kind (like indent guides) don't start a run of their own.
+    pub fn styled_runs(self) -> Result<Vec<(String, Option<SyntaxKind>)>, HighlightError> {
+        let code = self.code;
+        let (document, offsets) = self.generate_with_byte_offsets()?;
+
+        let mut spans: Vec<_> = document
+            .occurrences
+            .iter()
+            .zip(offsets)
+            .filter(|(occurrence, (start, end))| {
+                occurrence.syntax_kind != SyntaxKind::UnspecifiedSyntaxKind && start < end
+            })
+            .map(|(occurrence, (start, end))| (start, end, occurrence.syntax_kind))
+            .collect();
+        spans.sort_by_key(|&(start, end, _)| (start, end));
+
+        let mut runs: Vec<(String, Option<SyntaxKind>)> = Vec::new();
+        let mut push = |text: &str, kind| match runs.last_mut() {
+            Some((last, last_kind)) if *last_kind == kind => last.push_str(text),
+            _ if text.is_empty() => {}
+            _ => runs.push((text.to_string(), kind)),
+        };
+        let mut position = 0;
+        for (start, end, kind) in spans {
+            // Occurrences don't overlap, but be safe about positions that
+            // snapped to the same character.
+            let start = start.max(position);
+            if start >= end {
+                continue;
+            }
+            push(&code[position..start], None);
+            push(&code[start..end], Some(kind));
+            position = end;
+        }
+        push(&code[position..], None);
+        Ok(runs)
+    }
+
+    /// Like `generate`, but also returns the foreground color of each
+    /// occurrence in `theme`, as `(index of the occurrence, "#rrggbb")`.
+    ///
+    /// The colors are resolved from the same scope stack as the kinds, so
+    /// they match what syntect's own highlighter would show for the start of
+    /// each occurrence.
+    pub fn generate_with_colors(
+        mut self,
+        theme: &'a Theme,
+    ) -> Result<(Document, Vec<(usize, String)>), HighlightError> {
+        self.highlighter = Some(Highlighter::new(theme));
+        let (document, _) = self.generate_document()?;
+        Ok((document, std::mem::take(&mut self.colors)))
+    }
+
+    /// Like `generate`, but stops once `cancelled` is set, e.g. because the
+    /// client that asked for the document went away. The flag is checked
+    /// before every line; once it is set, the occurrences of the lines
+    /// before it are returned.
+    pub fn generate_cancellable(
+        mut self,
+        cancelled: &AtomicBool,
+    ) -> Result<Document, HighlightError> {
+        let (document, _) = self.generate_document_until(|| cancelled.load(Ordering::Relaxed))?;
+        Ok(document)
+    }
+
+    /// Like `generate`, but gives up once generating the document takes
+    /// longer than `timeout`, e.g. because a syntax is pathologically slow
+    /// for the code. The time is checked before every line, so a single
+    /// line that never finishes parsing is not interrupted.
+    pub fn generate_with_timeout(mut self, timeout: Duration) -> Result<Document, HighlightError> {
+        let start = Instant::now();
+        let mut timed_out = false;
+        let (document, _) = self.generate_document_until(|| {
+            timed_out = start.elapsed() >= timeout;
+            timed_out
+        })?;
+
+        if timed_out {
+            return Err(HighlightError::Timeout {
+                timeout,
+                partial: document,
+            });
+        }
+        Ok(document)
+    }
+
+    /// Parses the code and returns the document along with the stats that
+    /// are only known while parsing (the lines and truncation).
+    fn generate_document(&mut self) -> Result<(Document, GenerateStats), HighlightError> {
+        self.generate_document_until(|| false)
+    }
+
+    /// Like `generate_document`, but stops before the first line for which
+    /// `cancelled` returns true.
+    pub(super) fn generate_document_until(
+        &mut self,
+        mut cancelled: impl FnMut() -> bool,
+    ) -> Result<(Document, GenerateStats), HighlightError> {
+        let mut document = Document::new();
+        let mut cursor = LineCursor::new(self.code);
+        while let Some(occurrences) = self.next_line(&mut cursor, &mut cancelled)? {
+            document.occurrences.extend(occurrences);
+        }
+        self.finish()?;
+
+        if cfg!(debug_assertions) {
+            assert_non_overlapping(&document);
+        }
+
+        let stats = GenerateStats {
+            lines: cursor.lines,
+            truncated: cursor.truncated,
+            ..Default::default()
+        };
+        Ok((document, stats))
+    }
+
+    /// Like `generate`, but only keeps the occurrences that start on a row for
+    /// which `keep_row` returns true, e.g. to only highlight the rows that
+    /// are visible. The other occurrences are dropped as each line is
+    /// parsed, so they are never collected into the document. Unlike
+    /// `with_line_range`, every line is still parsed, since the rows don't
+    /// have to be contiguous. The `max_occurrences` limit counts the dropped
+    /// occurrences too.
+    pub fn generate_filtered(
+        mut self,
+        keep_row: impl Fn(i32) -> bool,
+    ) -> Result<Document, HighlightError> {
+        let mut document = Document::new();
+        let mut cursor = LineCursor::new(self.code);
+        while let Some(occurrences) = self.next_line(&mut cursor, &mut || false)? {
+            document.occurrences.extend(
+                occurrences
+                    .into_iter()
+                    .filter(|occurrence| keep_row(occurrence.range[0])),
+            );
+        }
+        self.finish()?;
+        Ok(document)
+    }
+
+    /// Returns the occurrences one at a time, parsing the next line only once
+    /// the occurrences of the previous one have been consumed. This yields
+    /// the same occurrences as `generate`, without holding all of them in
+    /// memory at once.
+    ///
+    /// The errors of `generate` are yielded in place of the occurrences of
+    /// the line that caused them, or after the last occurrence for
+    /// unbalanced highlights at the end of the file. The iterator ends after
+    /// the first error.
+    pub fn occurrences(self) -> impl Iterator<Item = Result<Occurrence, HighlightError>> + 'a {
+        let mut cursor = LineCursor::new(self.code);
+        let mut generator = self;
+        let mut line = Vec::new().into_iter();
+        let mut done = false;
+        std::iter::from_fn(move || loop {
+            if let Some(occurrence) = line.next() {
+                return Some(Ok(occurrence));
+            }
+            if done {
+                return None;
+            }
+            match generator.next_line(&mut cursor, &mut || false) {
+                Ok(Some(occurrences)) => line = occurrences.into_iter(),
+                Ok(None) => {
+                    done = true;
+                    return generator.finish().err().map(Err);
+                }
+                Err(err) => {
+                    done = true;
+                    return Some(Err(err));
+                }
+            }
+        })
+    }
+
+    /// Parses the line after `cursor` and returns its occurrences, or None
+    /// once there are no more lines to parse, because the end of the code or
+    /// line range was reached, a limit was hit or `cancelled` returned true.
+    ///
+    /// The returned occurrences are empty for lines outside of the line
+    /// range and once the document is full.
+    fn next_line(
+        &mut self,
+        cursor: &mut LineCursor<'a>,
+        cancelled: &mut impl FnMut() -> bool,
+    ) -> Result<Option<Vec<Occurrence>>, HighlightError> {
+        let (row, line_contents) = match cursor.rows.next() {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if row < self.initial_state.row {
+            return Ok(Some(Vec::new()));
+        }
+        let in_range = match &self.line_range {
+            Some(line_range) if row >= line_range.end => return Ok(None),
+            Some(line_range) => row >= line_range.start,
+            None => true,
+        };
+
+        cursor.total_bytes += line_contents.len();
+        if self.limits.max_lines.map_or(false, |n| row >= n)
+            || self
+                .limits
+                .max_total_bytes
+                .map_or(false, |n| cursor.total_bytes > n)
+            || cancelled()
+        {
+            cursor.truncated = true;
+            return Ok(None);
+        }
+
+        // The rest of the file is still parsed once the document is full, so
+        // that the highlights stay balanced.
+        if self.line_states.is_some() {
+            let state = LineState {
+                line: line_contents,
+                state: Checkpoint::new(self, row),
+            };
+            self.line_states.get_or_insert_with(Vec::new).push(state);
+        }
+        let mut occurrences = self.process_line(row, line_contents)?;
+        if self.checkpoints.is_some() {
+            let checkpoint = Checkpoint::new(self, row + 1);
+            self.checkpoints
+                .get_or_insert_with(Vec::new)
+                .push(checkpoint);
+        }
+        cursor.lines += 1;
+        if !in_range {
+            return Ok(Some(Vec::new()));
+        }
+        if self.coalescing {
+            coalesce_occurrences(&mut occurrences);
+        }
+
+        let remaining = match self.limits.max_occurrences {
+            Some(max) => max.saturating_sub(cursor.occurrences),
+            None => usize::MAX,
+        };
+        if occurrences.len() > remaining {
+            cursor.truncated = true;
+            occurrences.truncate(remaining);
+        }
+        if self.highlighter.is_some() {
+            for (i, occurrence) in occurrences.iter().enumerate() {
+                if let Some(color) = style_at(&self.line_styles, occurrence.range[1]) {
+                    self.colors.push((cursor.occurrences + i, hex_color(color)));
+                }
+            }
+        }
+        cursor.occurrences += occurrences.len();
+        Ok(Some(occurrences))
+    }
+
+    /// Parses the next line of the file and returns the occurrences for it.
+    ///
+    /// Lines must be passed in order, starting at row 0, and must include
+    /// their line ending (see `syntect::util::LinesWithEndings`). Call
+    /// `finish` after the last line.
+    pub fn process_line(
+        &mut self,
+        row: usize,
+        line_contents: &str,
+    ) -> Result<Vec<Occurrence>, HighlightError> {
+        // Plain text has no scopes to highlight, so there is no need to parse it.
+        if self.plain_text {
+            return Ok(Vec::new());
+        }
+
+        let checked_row = self.checked_row(row, line_contents)?;
+        let indentation = if self.python && self.indent_guides {
+            self.indentation(checked_row, line_contents)
+        } else {
+            None
+        };
+
+        let mut occurrences = self.parse_line(checked_row, line_contents);
+        if let Some(indentation) = indentation {
+            occurrences.insert(0, indentation);
+        }
+        if self.markdown {
+            self.process_fence_line(row, line_contents, &mut occurrences)?;
+        }
+        Ok(occurrences)
+    }
+
+    /// Returns `row` as an i32, the type of the positions in occurrence
+    /// ranges, if it and every column of `line` fit in one.
+    fn checked_row(&self, row: usize, line: &str) -> Result<i32, HighlightError> {
+        if !self.columns_fit(line) {
+            return Err(HighlightError::PositionOverflow { row });
+        }
+        checked_row(row)
+    }
+
+    /// Returns whether every column of `line` fits in an i32, the type of the
+    /// positions in occurrence ranges. Lines that are skipped because of
+    /// `max_line_len` have no columns.
+    fn columns_fit(&self, line: &str) -> bool {
+        if self.limits.max_line_len.map_or(false, |n| line.len() > n) {
+            return true;
+        }
+
+        // No encoding has more columns than bytes, except for expanded tabs.
+        let line = trim_line_ending(line);
+        let max_columns = match self.column_encoding {
+            ColumnEncoding::ExpandedTabs { tab_width } => {
+                line.len().saturating_mul(tab_width.max(1))
+            }
+            _ => line.len(),
+        };
+        max_columns <= i32::MAX as usize || self.column_encoding.len(line) <= i32::MAX as usize
+    }
+
+    /// Returns the occurrence for the indentation of a line, if it has any.
+    /// This has to be called before the line is parsed, while the highlights
+    /// still tell whether the line starts inside of a string.
+    fn indentation(&self, row: i32, line_contents: &str) -> Option<Occurrence> {
+        let line = trim_line_ending(line_contents);
+        let code_start = line.find(|c: char| c != ' ' && c != '\t')?;
+        if code_start == 0
+            || self.highlight_manager.innermost_kind() == Some(SyntaxKind::StringLiteral)
+        {
+            return None;
+        }
+
+        let width = self.column_encoding.len(&line[..code_start]) as i32;
+        Some(new_occurrence(
+            vec![row, 0, row, width],
+            SyntaxKind::UnspecifiedSyntaxKind,
+            0,
+        ))
+    }
+
+    /// Parses a line whose row and columns were checked by `checked_row`.
+    fn parse_line(&mut self, row: i32, line_contents: &str) -> Vec<Occurrence> {
+        let mut occurrences = Vec::new();
+
+        self.line_styles.clear();
+        if let Some(highlighter) = &self.highlighter {
+            let style = highlighter.style_for_stack(self.stack.as_slice());
+            self.line_styles.push((0, style.foreground));
+        }
+
+        let depth = self.depth();
+        let mut depths = (depth, depth);
+
+        // Lines that are too long (usually minified code) are not parsed
+        // and get no occurrences. Open highlights resume on the next line.
+        if self
+            .limits
+            .max_line_len
+            .map_or(false, |n| line_contents.len() > n)
+        {
+            self.highlight_manager.skip_line(row);
+            if let Some(depth_profile) = &mut self.depth_profile {
+                depth_profile.push(depths);
+            }
+            return occurrences;
+        }
+
+        let mut columns = LineColumns::new(line_contents, self.column_encoding);
+
+        // Every scope gets a highlight, so the two only drift apart when the
+        // ops don't make sense.
+        let imbalance = |generator: &Self| {
+            generator.highlight_manager.highlights.len() as isize - generator.stack.len() as isize
+        };
+        let imbalance_before = imbalance(self);
+        let mut unexpected_pop = false;
+
+        let ops = self.parse_state.parse_line(line_contents, self.syntax_set);
+        for &(byte_offset, ref op) in ops.as_slice() {
+            let character = columns.column(byte_offset);
+
+            let highlight_manager = &mut self.highlight_manager;
+            let scope_kinds = &mut self.scope_kinds;
+            let scope_mapping = &self.scope_mapping;
+            let ignore_scopes = &self.ignore_scopes;
+            let enabled_kinds = &self.enabled_kinds;
+            let unhandled_scopes = &mut self.unhandled_scopes;
+            let local_symbols = self.local_symbols;
+            let next_local_symbol = &mut self.next_local_symbol;
+            let diagnostics = self.diagnostics;
+            let outline = self.outline;
+            self.stack
+                .apply_with_hook(op, |basic_op, _| match basic_op {
+                    BasicScopeStackOp::Push(scope) => {
+                        let (kind, unhandled) = *scope_kinds.entry(scope).or_insert_with(|| {
+                            let scope_string = scope.build_string();
+                            let first_atom = scope_string.split('.').next().unwrap_or("");
+                            if ignore_scopes.contains(first_atom) {
+                                return (None, false);
+                            }
+
+                            let kind = lookup_scope(scope_mapping, &scope);
+                            let invalid = diagnostics && INVALID_SCOPE.is_prefix_of(scope);
+                            let unhandled = kind.is_none() && !invalid;
+                            let kind = kind
+                                .filter(|kind| {
+                                    enabled_kinds
+                                        .as_ref()
+                                        .map_or(true, |enabled| enabled.contains(kind))
+                                })
+                                .or_else(|| invalid.then(|| SyntaxKind::UnspecifiedSyntaxKind))
+                                .filter(|_| !outline || DEFINITION_SCOPE.is_prefix_of(scope));
+                            (kind, unhandled)
+                        });
+                        if unhandled {
+                            unhandled_scopes.insert(scope);
+                        }
+                        let kind = highlight_manager.nested_kind(kind);
+
+                        let is_definition = kind.is_some() && DEFINITION_SCOPE.is_prefix_of(scope);
+                        let symbol_roles = if is_definition {
+                            SymbolRole::Definition as i32
+                        } else {
+                            0
+                        };
+                        let symbol = if local_symbols && is_definition {
+                            *next_local_symbol += 1;
+                            format!("local {}", *next_local_symbol - 1)
+                        } else {
+                            String::new()
+                        };
+                        let diagnostic =
+                            if diagnostics && kind.is_some() && INVALID_SCOPE.is_prefix_of(scope) {
+                                Some(scope.build_string())
+                            } else {
+                                None
+                            };
+
+                        highlight_manager.push_hl(
+                            &mut occurrences,
+                            PartialHighlight {
+                                row,
+                                col: character,
+                                kind,
+                                symbol,
+                                symbol_roles,
+                                diagnostic,
+                                split: false,
+                            },
+                        );
+                    }
+                    BasicScopeStackOp::Pop => {
+                        if !highlight_manager.pop_hl(&mut occurrences, row, character) {
+                            unexpected_pop = true;
+                        }
+                    }
+                });
+
+            if let Some(highlighter) = &self.highlighter {
+                let style = highlighter.style_for_stack(self.stack.as_slice());
+                self.line_styles.push((character, style.foreground));
+            }
+            let depth = self.depth();
+            depths = (depths.0.min(depth), depths.1.max(depth));
+        }
+        if let Some(depth_profile) = &mut self.depth_profile {
+            depth_profile.push(depths);
+        }
+
+        if unexpected_pop || imbalance(self) != imbalance_before {
+            self.suspicious_lines.push(row as usize);
+        }
+
+        self.highlight_manager
+            .flush_end_of_line(&mut occurrences, row, columns.end_of_line);
+
+        occurrences
+    }
+
+    /// Opens and closes fenced code blocks in Markdown, and adds the
+    /// occurrences of the code inside of them.
+    fn process_fence_line(
+        &mut self,
+        row: usize,
+        line_contents: &str,
+        occurrences: &mut Vec<Occurrence>,
+    ) -> Result<(), HighlightError> {
+        let fence = match &mut self.fence {
+            Some(fence) => fence,
+            None => {
+                if let Some((marker, language)) = fence_opening(line_contents) {
+                    let generator = self
+                        .syntax_set
+                        .find_syntax_by_token(language)
+                        .map(|sr| Box::new(self.embedded_generator(sr)));
+                    self.fence = Some(Fence { marker, generator });
+                }
+                return Ok(());
+            }
+        };
+
+        let trimmed = line_contents.trim();
+        if trimmed.starts_with(&fence.marker)
+            && trimmed
+                .trim_start_matches(fence.marker.chars().next().unwrap())
+                .is_empty()
+        {
+            if let Some(generator) = &fence.generator {
+                self.unhandled_scopes
+                    .extend(generator.unhandled_scopes.iter().cloned());
+            }
+            self.fence = None;
+            return Ok(());
+        }
+
+        if let Some(generator) = &mut fence.generator {
+            generator.next_local_symbol = self.next_local_symbol;
+            occurrences.extend(generator.process_line(row, line_contents)?);
+            self.next_local_symbol = generator.next_local_symbol;
+        }
+        Ok(())
+    }
+
+    /// Creates a generator for code of another language inside of this file,
+    /// with the same configuration as this one.
+    fn embedded_generator(&self, sr: &SyntaxReference) -> DocumentGenerator<'a> {
+        let limits = LimitConfig::max_line_len(self.limits.max_line_len);
+        let mut generator = DocumentGenerator::new(self.syntax_set, sr, "", limits)
+            .with_column_encoding(self.column_encoding)
+            .with_local_symbols(self.local_symbols)
+            .with_emit_empty_ranges(self.highlight_manager.emit_empty_ranges)
+            .with_diagnostics(self.diagnostics)
+            .with_outline_mode(self.outline)
+            .with_indent_guides(self.indent_guides);
+        generator.scope_mapping = self.scope_mapping.clone();
+        generator.ignore_scopes = self.ignore_scopes.clone();
+        generator.enabled_kinds = self.enabled_kinds.clone();
+        generator
+    }
+
+    /// Moves the highlights that are still open to the start of `row`, for
+    /// callers of `process_line` that don't pass every line of a file in
+    /// order, like the `DiffDocumentGenerator`.
+    pub(crate) fn resume_at(&mut self, row: usize) -> Result<(), HighlightError> {
+        self.highlight_manager.skip_line(checked_row(row)? - 1);
+        Ok(())
+    }
+
+    /// Finishes the file after the last call to `process_line`.
+    ///
+    /// Highlights are closed at the end of every line, so there is nothing
+    /// left to emit; this only verifies that the highlights that are still
+    /// open match the scopes that syntect left open.
+    pub fn finish(&mut self) -> Result<(), HighlightError> {
+        let highlights = self.highlight_manager.highlights.len();
+        if highlights != self.stack.len() {
+            return Err(HighlightError::UnbalancedHighlights {
+                highlights,
+                scopes: self.stack.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// How far `DocumentGenerator::next_line` got through the lines of the code.
+struct LineCursor<'a> {
+    rows: Enumerate<LinesWithEndings<'a>>,
+    total_bytes: usize,
+    /// The number of lines that were parsed.
+    lines: usize,
+    /// The number of occurrences that were returned.
+    occurrences: usize,
+    truncated: bool,
+}
+
+impl<'a> LineCursor<'a> {
+    fn new(code: &'a str) -> Self {
+        LineCursor {
+            rows: LinesWithEndings::from(code).enumerate(),
+            total_bytes: 0,
+            lines: 0,
+            occurrences: 0,
+            truncated: false,
+        }
+    }
+}
+
+/// Returns the color in effect at `character`, given the colors after each
+/// op of the line.
+fn style_at(line_styles: &[(i32, Color)], character: i32) -> Option<Color> {
+    line_styles
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= character)
+        .map(|(_, color)| *color)
+}
+
+fn hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// Returns the fence marker and the language of a line that opens a fenced
+/// code block, like ```` ```go ````. The language is empty if there is none.
+fn fence_opening(line: &str) -> Option<(String, &str)> {
+    let line = line.trim_start();
+    let fence_char = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let marker_len = line.len() - line.trim_start_matches(fence_char).len();
+    if marker_len < 3 {
+        return None;
+    }
+
+    let (marker, info) = line.split_at(marker_len);
+    let language = info.split_whitespace().next().unwrap_or("");
+    Some((marker.to_string(), language))
+}
+
+/// Converts the byte offsets of syntect's ops on a line into columns of the
+/// configured encoding.
+pub(super) struct LineColumns<'l> {
+    line: &'l str,
+    encoding: ColumnEncoding,
+    end_of_line: i32,
+
+    // Ops are sorted by byte offset, so each column is counted from the
+    // previous op instead of from the start of the line.
+    last_offset: usize,
+    last_character: usize,
+}
+
+impl<'l> LineColumns<'l> {
+    pub(super) fn new(line: &'l str, encoding: ColumnEncoding) -> Self {
+        LineColumns {
+            line,
+            encoding,
+            end_of_line: encoding.len(trim_line_ending(line)) as i32,
+            last_offset: 0,
+            last_character: 0,
+        }
+    }
+
+    pub(super) fn column(&mut self, byte_offset: usize) -> i32 {
+        if byte_offset >= self.line.len() {
+            return self.end_of_line;
+        }
+
+        // An offset inside of a multibyte character snaps to the start of
+        // that character. Dropping the op instead would leave the highlights
+        // out of sync with syntect's scope stack.
+        let mut byte_offset = byte_offset;
+        while !self.line.is_char_boundary(byte_offset) {
+            byte_offset -= 1;
+        }
+        if self.encoding == ColumnEncoding::GraphemeCluster {
+            let mut cursor = GraphemeCursor::new(byte_offset, self.line.len(), true);
+            if !cursor.is_boundary(self.line, 0).unwrap_or(true) {
+                byte_offset = cursor
+                    .prev_boundary(self.line, 0)
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0);
+            }
+        }
+
+        if byte_offset < self.last_offset {
+            self.last_offset = 0;
+            self.last_character = 0;
+        }
+        self.last_character = self.encoding.advance(
+            self.last_character,
+            &self.line[self.last_offset..byte_offset],
+        );
+        self.last_offset = byte_offset;
+        self.last_character as i32
+    }
+}
+
+/// Merges documents that were generated for consecutive chunks of a file
+/// into a single document. Each chunk comes with the row that it starts at
+/// in the file, which is added to the rows of its occurrences.
+///
+/// Chunks are highlighted independently, so a chunk that starts inside of
+/// a multi-line construct (like a block comment) won't know about it.
+pub fn merge_documents(chunks: Vec<(usize, Document)>) -> Result<Document, HighlightError> {
+    let mut document = Document::new();
+    for (row_offset, chunk) in chunks {
+        let shift = |row: i32| checked_row(row_offset.saturating_add(row as usize));
+        for mut occurrence in chunk.occurrences.into_iter() {
+            occurrence.range[0] = shift(occurrence.range[0])?;
+            if occurrence.range.len() == 4 {
+                occurrence.range[2] = shift(occurrence.range[2])?;
+            }
+            document.occurrences.push(occurrence);
+        }
+    }
+
+    Ok(document)
+}
+
+/// Converts a row to an i32, the type of the positions in occurrence ranges.
+fn checked_row(row: usize) -> Result<i32, HighlightError> {
+    if row > i32::MAX as usize {
+        return Err(HighlightError::PositionOverflow { row });
+    }
+    Ok(row as i32)
+}
+
+/// Merges each occurrence into the previous one if it has the same kind and
+/// symbol and starts on the same line where the previous one ends. The
+/// occurrences must be sorted, like the ones of a line from `process_line`.
+fn coalesce_occurrences(occurrences: &mut Vec<Occurrence>) {
+    let mut coalesced: Vec<Occurrence> = Vec::with_capacity(occurrences.len());
+    for occurrence in occurrences.drain(..) {
+        if let Some(previous) = coalesced.last_mut() {
+            let adjacent = previous.range.len() == 3
+                && occurrence.range.len() == 3
+                && previous.range[0] == occurrence.range[0]
+                && previous.range[2] == occurrence.range[1];
+            if adjacent
+                && previous.syntax_kind == occurrence.syntax_kind
+                && previous.symbol == occurrence.symbol
+                && previous.symbol_roles == occurrence.symbol_roles
+                && previous.diagnostics == occurrence.diagnostics
+            {
+                previous.range[2] = occurrence.range[2];
+                continue;
+            }
+        }
+        coalesced.push(occurrence);
+    }
+    *occurrences = coalesced;
+}
+
+/// Panics if any two occurrences of `document` have intersecting ranges,
+/// listing every such pair. `generate` runs this in debug builds, since the
+/// `HighlightManager` is supposed to split nested scopes so they never
+/// overlap.
+pub fn assert_non_overlapping(document: &Document) {
+    let overlaps = overlapping_occurrences(document.get_occurrences());
+    if !overlaps.is_empty() {
+        let pairs: Vec<String> = overlaps
+            .iter()
+            .map(|(a, b)| {
+                format!(
+                    "{:?} {:?} overlaps {:?} {:?}",
+                    a.syntax_kind, a.range, b.syntax_kind, b.range
+                )
+            })
+            .collect();
+        panic!("overlapping occurrences:\n{}", pairs.join("\n"));
+    }
+}
+
+pub(super) fn overlapping_occurrences(occurrences: &[Occurrence]) -> Vec<(Occurrence, Occurrence)> {
+    let mut occurrences = occurrences.to_vec();
+    sort_occurrences(&mut occurrences);
+
+    // Ranges are half-open, so after sorting by start an occurrence overlaps
+    // an earlier one iff it starts before the furthest end seen so far.
+    let mut overlaps = Vec::new();
+    let mut furthest: Option<(&Occurrence, PackedRange)> = None;
+    for occurrence in &occurrences {
+        let range = PackedRange::from_vec(&occurrence.range);
+        if let Some((previous, previous_range)) = &furthest {
+            let (start, previous_end) = (
+                (range.start_line, range.start_col),
+                (previous_range.end_line, previous_range.end_col),
+            );
+            if start < previous_end {
+                overlaps.push(((*previous).clone(), occurrence.clone()));
+            }
+            if (range.end_line, range.end_col) <= previous_end {
+                continue;
+            }
+        }
+        furthest = Some((occurrence, range));
+    }
+
+    overlaps
+}
+
+pub(super) fn sort_occurrences(occurrences: &mut [Occurrence]) {
+    occurrences.sort_by_key(|o| PackedRange::from_vec(&o.range));
+}
+
+/// Returns the char of `line` that starts at column `col`.
+fn char_at_column(line: &str, encoding: ColumnEncoding, col: i32) -> Option<char> {
+    line[column_byte_offset(line, encoding, col)?..]
+        .chars()
+        .next()
+}
+
+/// Returns the byte offset of column `col` in `line`, which must not include
+/// its line ending. The end of the line is a valid column; columns past it
+/// or inside of a character (like the middle of an expanded tab) are not.
+fn column_byte_offset(line: &str, encoding: ColumnEncoding, col: i32) -> Option<usize> {
+    let col = col as usize;
+    let units: Box<dyn Iterator<Item = (usize, &str)>> = match encoding {
+        ColumnEncoding::GraphemeCluster => Box::new(line.grapheme_indices(true)),
+        _ => Box::new(
+            line.char_indices()
+                .map(|(offset, c)| (offset, &line[offset..offset + c.len_utf8()])),
+        ),
+    };
+
+    let mut column = 0;
+    for (offset, unit) in units {
+        if column >= col {
+            return (column == col).then(|| offset);
+        }
+        column = encoding.advance(column, unit);
+    }
+    (column == col).then(|| line.len())
+}
+
+/// The line ending (`\n` or `\r\n`) is not part of any highlight, so lines
+/// end before it.
+fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
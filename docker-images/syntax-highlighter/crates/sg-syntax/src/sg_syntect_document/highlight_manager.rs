@@ -0,0 +1,227 @@
+use sg_lsif::{Diagnostic, Occurrence, Severity, SyntaxKind};
+
+/// HighlightManager keeps track of the scopes that are currently open and
+/// splits them so that the emitted occurrences never overlap.
+///
+/// For example, a string containing punctuation:
+///
+/// ```text
+///   "hello, world"
+///   ^^^^^^^^^^^^^^ string
+///         ^        punctuation
+/// ```
+///
+/// is emitted as three occurrences: `"hello` (string), `,` (punctuation) and
+/// ` world"` (string).
+///
+/// The splitting doesn't depend on what the kinds are, so it works with any
+/// kind type `K`; the generator uses `SyntaxKind` and emits `Occurrence`s.
+#[derive(Debug, Default)]
+pub(super) struct HighlightManager<K = SyntaxKind> {
+    pub(super) highlights: Vec<PartialHighlight<K>>,
+    pub(super) emit_empty_ranges: bool,
+}
+
+impl HighlightManager {
+    /// Returns the kind that a scope of `kind` gets when it is pushed inside
+    /// of the currently open highlights.
+    pub(super) fn nested_kind(&self, kind: Option<SyntaxKind>) -> Option<SyntaxKind> {
+        match self.innermost_kind() {
+            // Comments and keys (like the quoted keys of JSON objects) are
+            // emitted as a single occurrence, so anything nested inside of
+            // them is not highlighted.
+            Some(SyntaxKind::Comment) | Some(SyntaxKind::IdentifierAttribute) => None,
+            // A scope of the same kind as its enclosing highlight (like the
+            // quotes of a string) doesn't split it.
+            innermost if innermost == kind => None,
+            _ => kind,
+        }
+    }
+}
+
+impl<K: Clone> HighlightManager<K> {
+    /// Returns the kind of the innermost open highlight that has a kind.
+    pub(super) fn innermost_kind(&self) -> Option<K> {
+        self.highlights.iter().rev().find_map(|hl| hl.kind.clone())
+    }
+
+    /// Returns the number of open highlights, which is one per open scope.
+    pub(super) fn depth(&self) -> usize {
+        self.highlights.len()
+    }
+
+    fn last_highlighted_mut(&mut self) -> Option<&mut PartialHighlight<K>> {
+        self.highlights
+            .iter_mut()
+            .rev()
+            .find(|hl| hl.kind.is_some())
+    }
+
+    pub(super) fn push_hl(
+        &mut self,
+        occurrences: &mut impl OccurrenceSink<K>,
+        partial_hl: PartialHighlight<K>,
+    ) {
+        // The enclosing highlight ends where the new one starts. It resumes
+        // once the new highlight is popped. If nothing of it comes before
+        // the new one, that is not an empty scope, so nothing is emitted.
+        if partial_hl.kind.is_some() {
+            if let Some(existing_hl) = self.last_highlighted_mut() {
+                push_document_occurrence(
+                    occurrences,
+                    existing_hl,
+                    partial_hl.row,
+                    partial_hl.col,
+                    false,
+                );
+            }
+        }
+
+        self.highlights.push(partial_hl);
+    }
+
+    /// Closes the innermost highlight. Returns false if there was none.
+    pub(super) fn pop_hl(
+        &mut self,
+        occurrences: &mut impl OccurrenceSink<K>,
+        row: i32,
+        col: i32,
+    ) -> bool {
+        let partial_hl = match self.highlights.pop() {
+            Some(partial_hl) => partial_hl,
+            None => return false,
+        };
+
+        if partial_hl.kind.is_some() {
+            // Only a scope that ends where it was pushed is really empty. The
+            // rest of a split highlight can be empty too, like a string that
+            // ends right after an escape, but the scope itself isn't.
+            let emit_empty_ranges = self.emit_empty_ranges && !partial_hl.split;
+            push_document_occurrence(occurrences, &partial_hl, row, col, emit_empty_ranges);
+
+            if let Some(existing_hl) = self.last_highlighted_mut() {
+                if (existing_hl.row, existing_hl.col) != (row, col) {
+                    existing_hl.row = row;
+                    existing_hl.col = col;
+                    existing_hl.split = true;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Closes the innermost highlight at the end of the line and moves all
+    /// open highlights to the start of the next line, so that no occurrence
+    /// spans multiple lines.
+    pub(super) fn flush_end_of_line(
+        &mut self,
+        occurrences: &mut impl OccurrenceSink<K>,
+        row: i32,
+        end_of_line: i32,
+    ) {
+        if let Some(existing_hl) = self.last_highlighted_mut() {
+            push_document_occurrence(occurrences, existing_hl, row, end_of_line, false);
+        }
+
+        self.skip_line(row);
+    }
+
+    /// Moves all open highlights to the start of the line after `row`.
+    pub(super) fn skip_line(&mut self, row: i32) {
+        for hl in self.highlights.iter_mut() {
+            hl.row = row + 1;
+            hl.col = 0;
+            hl.split = true;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct PartialHighlight<K = SyntaxKind> {
+    pub(super) row: i32,
+    pub(super) col: i32,
+    pub(super) kind: Option<K>,
+    // Empty unless local symbols are enabled and this is a definition.
+    pub(super) symbol: String,
+    pub(super) symbol_roles: i32,
+    // The invalid scope, if diagnostics are enabled and this is one.
+    pub(super) diagnostic: Option<String>,
+    // Whether `row` and `col` were moved past the start of the scope, after
+    // a nested highlight or at the start of a line.
+    pub(super) split: bool,
+}
+
+/// Receives the highlights that the `HighlightManager` has split, as
+/// `[start_line, start_character, end_line, end_character]` ranges (or
+/// `[line, character, character]` for empty ranges).
+pub(super) trait OccurrenceSink<K> {
+    fn push_highlight(&mut self, partial_hl: &PartialHighlight<K>, kind: K, range: Vec<i32>);
+}
+
+impl OccurrenceSink<SyntaxKind> for Vec<Occurrence> {
+    fn push_highlight(&mut self, partial_hl: &PartialHighlight, kind: SyntaxKind, range: Vec<i32>) {
+        let mut occurrence = new_occurrence(range, kind, partial_hl.symbol_roles);
+        occurrence.symbol = partial_hl.symbol.clone();
+        if let Some(scope) = &partial_hl.diagnostic {
+            occurrence.diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                code: scope.clone(),
+                message: "Invalid syntax".to_string(),
+                source: "syntect".to_string(),
+                ..Default::default()
+            });
+        }
+        self.push(occurrence);
+    }
+}
+
+fn push_document_occurrence<K: Clone>(
+    occurrences: &mut impl OccurrenceSink<K>,
+    partial_hl: &PartialHighlight<K>,
+    row: i32,
+    col: i32,
+    emit_empty_ranges: bool,
+) {
+    let kind = match &partial_hl.kind {
+        Some(kind) => kind.clone(),
+        None => return,
+    };
+
+    // Empty ranges don't highlight anything, so they are only emitted when
+    // asked for, as single positions.
+    let range = if partial_hl.row == row && partial_hl.col == col {
+        if !emit_empty_ranges {
+            return;
+        }
+        vec![row, col, col]
+    } else {
+        vec![partial_hl.row, partial_hl.col, row, col]
+    };
+
+    occurrences.push_highlight(partial_hl, kind, range);
+}
+
+/// Creates an occurrence for a `[start_line, start_character, end_line,
+/// end_character]` range. Ranges within a single line use the shorter
+/// `[line, start_character, end_character]` form that SCIP allows.
+pub(super) fn new_occurrence(
+    range: Vec<i32>,
+    syntax_kind: SyntaxKind,
+    symbol_roles: i32,
+) -> Occurrence {
+    let range = match range[..] {
+        [start_line, start_character, end_line, end_character] if start_line == end_line => {
+            vec![start_line, start_character, end_character]
+        }
+        _ => range,
+    };
+
+    Occurrence {
+        range,
+        syntax_kind,
+        symbol: String::default(),
+        symbol_roles,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,86 @@
+use std::{fmt, time::Duration};
+
+use sg_lsif::Document;
+
+mod generator;
+mod highlight_manager;
+mod scope_mapping;
+#[cfg(test)]
+mod test;
+
+pub use generator::{
+    assert_non_overlapping, merge_documents, Checkpoint, ColumnEncoding, DocumentGenerator,
+    GenerateStats, HighlightEngine, LimitConfig,
+};
+pub use scope_mapping::{load_scope_mapping, ScopeMappingError};
+
+/// Errors that can occur while generating a `Document`.
+#[derive(Debug, PartialEq)]
+pub enum HighlightError {
+    /// The highlights that were still open at the end of the file don't match
+    /// the scopes that syntect left open, so some ops were lost along the way.
+    UnbalancedHighlights { highlights: usize, scopes: usize },
+    /// A row or column on line `row` is past `i32::MAX`, which is the largest
+    /// position that an occurrence range can hold.
+    PositionOverflow { row: usize },
+    /// The syntax for a query could not be determined.
+    Language(LanguageError),
+    /// Generating the document took longer than `timeout`. `partial` has the
+    /// occurrences of the lines that were parsed before that.
+    Timeout {
+        timeout: Duration,
+        partial: Document,
+    },
+}
+
+impl fmt::Display for HighlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighlightError::UnbalancedHighlights { highlights, scopes } => write!(
+                f,
+                "unhandled highlights: {} highlights are open for {} scopes",
+                highlights, scopes
+            ),
+            HighlightError::PositionOverflow { row } => {
+                write!(f, "position on line {} does not fit in an i32", row)
+            }
+            HighlightError::Language(err) => err.fmt(f),
+            HighlightError::Timeout { timeout, .. } => {
+                write!(f, "highlighting timed out after {:?}", timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HighlightError {}
+
+impl From<LanguageError> for HighlightError {
+    fn from(err: LanguageError) -> Self {
+        HighlightError::Language(err)
+    }
+}
+
+/// Errors that can occur while picking the syntax for a `SourcegraphQuery`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LanguageError {
+    /// None of the syntaxes matched the query's filetype, file path,
+    /// extension or first line.
+    NoMatchingLanguage { filepath: String, extension: String },
+}
+
+impl fmt::Display for LanguageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LanguageError::NoMatchingLanguage {
+                filepath,
+                extension,
+            } => write!(
+                f,
+                "no language matched filepath {:?} with extension {:?}",
+                filepath, extension
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LanguageError {}
@@ -0,0 +1,76 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use protobuf::ProtobufEnum;
+use sg_lsif::SyntaxKind;
+use syntect::parsing::{Scope, SCOPE_REPO};
+
+/// Errors that can occur while loading a scope mapping, see
+/// `load_scope_mapping`.
+#[derive(Debug)]
+pub enum ScopeMappingError {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The file is not a JSON object with string values.
+    Json(serde_json::Error),
+    /// The kind for `scope` is not the name of a `SyntaxKind`.
+    UnknownKind { scope: String, kind: String },
+}
+
+impl fmt::Display for ScopeMappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScopeMappingError::Io(err) => write!(f, "failed to read scope mapping: {}", err),
+            ScopeMappingError::Json(err) => write!(f, "invalid scope mapping: {}", err),
+            ScopeMappingError::UnknownKind { scope, kind } => {
+                write!(f, "unknown syntax kind {:?} for scope {:?}", kind, scope)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScopeMappingError {}
+
+/// Loads a scope mapping for `DocumentGenerator::with_scope_mapping` from a
+/// JSON file. The file is an object from scope prefixes to the names of
+/// `SyntaxKind`s, like `{"keyword": "IdentifierKeyword"}`.
+pub fn load_scope_mapping(path: &Path) -> Result<HashMap<String, SyntaxKind>, ScopeMappingError> {
+    let json = std::fs::read_to_string(path).map_err(ScopeMappingError::Io)?;
+    parse_scope_mapping(&json)
+}
+
+pub(super) fn parse_scope_mapping(
+    json: &str,
+) -> Result<HashMap<String, SyntaxKind>, ScopeMappingError> {
+    let names: HashMap<String, String> =
+        serde_json::from_str(json).map_err(ScopeMappingError::Json)?;
+    names
+        .into_iter()
+        .map(|(scope, kind)| {
+            match SyntaxKind::values()
+                .iter()
+                .find(|value| value.descriptor().name() == kind)
+            {
+                Some(&value) => Ok((scope, value)),
+                None => Err(ScopeMappingError::UnknownKind { scope, kind }),
+            }
+        })
+        .collect()
+}
+
+/// Finds the `SyntaxKind` for a scope by looking up the longest prefix of
+/// its atoms that is in `scope_mapping`. For `keyword.operator.arithmetic.go`
+/// this tries `keyword.operator.arithmetic.go`, then
+/// `keyword.operator.arithmetic` and so on, down to just `keyword`.
+pub(super) fn lookup_scope(
+    scope_mapping: &HashMap<String, SyntaxKind>,
+    scope: &Scope,
+) -> Option<SyntaxKind> {
+    let repo = SCOPE_REPO.lock().unwrap();
+    let atoms: Vec<&str> = (0..scope.len())
+        .map(|i| repo.atom_str(scope.atom_at(i as usize)))
+        .collect();
+
+    (1..=atoms.len())
+        .rev()
+        .find_map(|len| scope_mapping.get(&atoms[..len].join(".")).copied())
+}
@@ -0,0 +1,14 @@
+// Package main is the entrypoint.
+package main
+
+/*
+Block comments span
+multiple lines.
+*/
+import "fmt"
+
+// main prints the answer.
+func main() {
+	x := 42 // the answer
+	fmt.Println("Answer:", x) /* inline */
+}
@@ -0,0 +1,10 @@
+package main
+
+func doThing(n int) int {
+	return n * 2
+}
+
+func main() {
+	result := doThing(21)
+	println(result)
+}
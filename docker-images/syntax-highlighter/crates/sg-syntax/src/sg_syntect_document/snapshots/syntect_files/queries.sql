@@ -0,0 +1,5 @@
+-- Users that signed up recently.
+CREATE TABLE users (id INT PRIMARY KEY, name VARCHAR(20));
+
+SELECT * FROM t WHERE x = 1;
+SELECT name, count(*) FROM users WHERE name <> 'admin' GROUP BY name;
@@ -0,0 +1,2002 @@
+use std::{
+    collections::HashMap,
+    fs::{read_dir, File},
+    io::Read,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use sg_lsif::{Document, Occurrence, Severity, SymbolRole, SyntaxKind};
+use syntect::{parsing::SyntaxSet, util::LinesWithEndings};
+
+use super::{
+    generator::{overlapping_occurrences, sort_occurrences, LineColumns},
+    highlight_manager::{new_occurrence, HighlightManager, OccurrenceSink, PartialHighlight},
+    scope_mapping::parse_scope_mapping,
+    *,
+};
+use crate::{dump_document, dump_document_compact, SourcegraphQuery};
+
+/// A generator for `code` in the language of `extension`, without limits.
+fn generator<'a>(
+    syntax_set: &'a SyntaxSet,
+    extension: &str,
+    code: &'a str,
+) -> DocumentGenerator<'a> {
+    let syntax_reference = syntax_set.find_syntax_by_extension(extension).unwrap();
+    DocumentGenerator::new(syntax_set, syntax_reference, code, LimitConfig::default())
+}
+
+fn generate(extension: &str, code: &str) -> Document {
+    crate::SYNTAX_SET.with(|syntax_set| generator(syntax_set, extension, code).generate().unwrap())
+}
+
+/// The ranges of the occurrences of `kind` in `code`, in the language of
+/// `extension`.
+fn occurrences_of(extension: &str, code: &str, kind: SyntaxKind) -> Vec<Vec<i32>> {
+    generate(extension, code)
+        .occurrences
+        .iter()
+        .filter(|o| o.syntax_kind == kind)
+        .map(|o| o.range.clone())
+        .collect()
+}
+
+#[test]
+fn test_generates_line_comment() {
+    let src = "package main\n// Hello World\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::Comment),
+        vec![vec![1, 0, 14]]
+    );
+}
+
+#[test]
+fn test_generates_numeric_literal() {
+    let src = "package main\nfunc main() {\n\tx := 42\n}\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::NumericLiteral),
+        vec![vec![2, 6, 8]]
+    );
+}
+
+#[test]
+fn test_generates_operators() {
+    let src = "package main\nfunc main() {\n\ta := b + c\n}\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::IdentifierOperator),
+        vec![vec![2, 3, 5], vec![2, 8, 9]]
+    );
+}
+
+#[test]
+fn test_skips_lines_longer_than_max_line_len() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let syntax_reference = syntax_set.find_syntax_by_extension("go").unwrap();
+    let src = "// a\n// this comment is much longer than the limit\n";
+    let document = DocumentGenerator::new(
+        &syntax_set,
+        syntax_reference,
+        src,
+        LimitConfig::max_line_len(Some(5)),
+    )
+    .generate()
+    .unwrap();
+
+    let ranges: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|o| o.range.clone())
+        .collect();
+    assert_eq!(ranges, vec![vec![0, 0, 4]]);
+}
+
+#[test]
+fn test_custom_scope_mapping() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main // comment\n";
+    let scope_mapping = [("comment".to_string(), SyntaxKind::StringLiteral)]
+        .into_iter()
+        .collect();
+    let document = generator(&syntax_set, "go", src)
+        .with_scope_mapping(scope_mapping)
+        .generate()
+        .unwrap();
+
+    let occurrences: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|o| (o.range.clone(), o.syntax_kind))
+        .collect();
+    assert_eq!(
+        occurrences,
+        vec![(vec![0, 13, 23], SyntaxKind::StringLiteral)]
+    );
+}
+
+#[test]
+fn test_longest_prefix_scope_mapping() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nfunc main() {\n\tif a := b + c; a {\n\t}\n}\n";
+    let scope_mapping = [
+        ("keyword", SyntaxKind::Identifier),
+        ("keyword.control", SyntaxKind::IdentifierKeyword),
+        ("keyword.operator", SyntaxKind::IdentifierOperator),
+        ("keyword.operator.initialize", SyntaxKind::IdentifierBuiltin),
+    ]
+    .into_iter()
+    .map(|(scope, kind)| (scope.to_string(), kind))
+    .collect();
+    let document = generator(&syntax_set, "go", src)
+        .with_scope_mapping(scope_mapping)
+        .generate()
+        .unwrap();
+
+    let occurrences: Vec<_> = document
+        .occurrences
+        .iter()
+        .filter(|o| o.range[0] == 2)
+        .map(|o| (o.range.clone(), o.syntax_kind))
+        .collect();
+    assert_eq!(
+        occurrences,
+        vec![
+            (vec![2, 1, 3], SyntaxKind::IdentifierKeyword),
+            (vec![2, 6, 8], SyntaxKind::IdentifierBuiltin),
+            (vec![2, 11, 12], SyntaxKind::IdentifierOperator),
+        ]
+    );
+}
+
+#[test]
+fn test_generates_punctuation_delimiters() {
+    let src = "f(a, b);\n";
+    insta::assert_snapshot!(dump_document(&generate("js", src), src));
+}
+
+#[test]
+fn test_generates_regex_literals() {
+    let src = "const r = /ab+c|\\d/;\n";
+    insta::assert_snapshot!(dump_document(&generate("js", src), src));
+}
+
+#[test]
+fn test_bracket_pairs() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "f(((x)));\n";
+    let (document, pairs) = generator(&syntax_set, "js", src)
+        .generate_with_bracket_pairs()
+        .unwrap();
+
+    let mut columns: Vec<_> = pairs
+        .iter()
+        .map(|(&open, &close)| {
+            (
+                document.occurrences[open].range[1],
+                document.occurrences[close].range[1],
+            )
+        })
+        .collect();
+    columns.sort();
+    assert_eq!(columns, vec![(1, 7), (2, 6), (3, 5)]);
+}
+
+#[test]
+fn test_generates_type_names() {
+    let src = "package main\ntype Foo struct {}\ntype Bar int\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::IdentifierType),
+        vec![vec![1, 5, 8], vec![2, 5, 8]]
+    );
+}
+
+#[test]
+fn test_generates_builtins() {
+    let src = "package main\nfunc main() {\n\tlen(x)\n}\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::IdentifierBuiltin),
+        vec![vec![2, 1, 4]]
+    );
+}
+
+#[test]
+fn test_generates_language_constants() {
+    let src = "package main\nfunc main() {\n\tx := true\n}\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::IdentifierBuiltin),
+        vec![vec![2, 6, 10]]
+    );
+
+    let src = "a = true; b = null;\n";
+    insta::assert_snapshot!(dump_document(&generate("js", src), src));
+}
+
+#[test]
+fn test_splits_strings_around_escapes() {
+    let src = "package main\nvar s = \"a\\nb\"\n";
+    insta::assert_snapshot!(dump_document(&generate("go", src), src));
+}
+
+#[test]
+fn test_sorted_random_nested_scopes() {
+    // A small linear congruential generator, so the test is reproducible.
+    let mut seed: u64 = 0x5eed;
+    let mut random = |n: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % n
+    };
+    fn expression(random: &mut impl FnMut(u64) -> u64, depth: u32) -> String {
+        match random(if depth == 0 { 3 } else { 7 }) {
+            0 => "1".to_string(),
+            1 => "\"a\\tb\"".to_string(),
+            2 => "/* c */ x".to_string(),
+            3 => format!(
+                "f({}, {})",
+                expression(random, depth - 1),
+                expression(random, depth - 1)
+            ),
+            4 => format!("[]int{{{}}}", expression(random, depth - 1)),
+            5 => format!("func() {{\n\treturn {}\n}}", expression(random, depth - 1)),
+            _ => format!("({})", expression(random, depth - 1)),
+        }
+    }
+
+    let sources: Vec<String> = (0..100)
+        .map(|_| {
+            let mut src = "package main\n".to_string();
+            for _ in 0..5 {
+                src += &format!("var x = {}\n", expression(&mut random, 4));
+            }
+            src
+        })
+        .collect();
+
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let is_sorted = |occurrences: &[Occurrence]| {
+        occurrences.windows(2).all(|pair| {
+            (pair[0].range[0], pair[0].range[1]) <= (pair[1].range[0], pair[1].range[1])
+        })
+    };
+
+    for src in &sources {
+        let sorted = generator(&syntax_set, "go", src)
+            .generate_sorted()
+            .unwrap()
+            .occurrences
+            .into_vec();
+        assert!(is_sorted(&sorted), "{}", src);
+
+        let mut expected = generator(&syntax_set, "go", src)
+            .generate()
+            .unwrap()
+            .occurrences
+            .into_vec();
+        expected.sort_by_key(|o| (o.range[0], o.range[1]));
+        assert_eq!(sorted, expected, "{}", src);
+
+        // `generate` happens to emit these in order already, so check
+        // that the sort does put reversed occurrences back in order.
+        let mut reversed = sorted.clone();
+        reversed.reverse();
+        assert!(sorted.len() < 2 || !is_sorted(&reversed));
+        sort_occurrences(&mut reversed);
+        assert_eq!(reversed, sorted);
+    }
+}
+
+#[test]
+fn test_generate_sorted() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nvar s = \"a\\nb\" // c\n";
+    let document = generator(&syntax_set, "go", src).generate_sorted().unwrap();
+
+    let mut expected = generate("go", src).occurrences.into_vec();
+    sort_occurrences(&mut expected);
+    assert_eq!(document.occurrences.into_vec(), expected);
+}
+
+#[test]
+fn test_string_containing_punctuation_does_not_overlap() {
+    let src = "f(\"hello, ${world}\", `a${b[0]}c`);\n";
+    let document = generate("js", src);
+
+    assert!(document.occurrences.len() > 3);
+    assert_non_overlapping(&document);
+}
+
+#[test]
+fn test_detects_overlapping_occurrences() {
+    let occurrences = vec![
+        new_occurrence(vec![0, 0, 10], SyntaxKind::StringLiteral, 0),
+        new_occurrence(vec![0, 6, 7], SyntaxKind::PunctuationBracket, 0),
+        new_occurrence(vec![0, 10, 1, 2], SyntaxKind::Comment, 0),
+        new_occurrence(vec![1, 1, 3], SyntaxKind::Identifier, 0),
+    ];
+
+    let overlaps: Vec<_> = overlapping_occurrences(&occurrences)
+        .into_iter()
+        .map(|(a, b)| (a.range, b.range))
+        .collect();
+    assert_eq!(
+        overlaps,
+        vec![
+            (vec![0, 0, 10], vec![0, 6, 7]),
+            (vec![0, 10, 1, 2], vec![1, 1, 3]),
+        ]
+    );
+}
+
+#[test]
+fn test_generates_attributes() {
+    for (extension, src, expected) in [
+        (
+            "py",
+            "@cached\ndef f(): pass\n",
+            vec![vec![0, 0, 1], vec![0, 1, 7]],
+        ),
+        (
+            "java",
+            "class A {\n  @Override\n  void f() {}\n}\n",
+            vec![vec![1, 2, 3], vec![1, 3, 11]],
+        ),
+        (
+            "rs",
+            "#[derive(Debug)]\nstruct A;\n",
+            vec![vec![0, 0, 1], vec![0, 2, 8]],
+        ),
+    ] {
+        assert_eq!(
+            occurrences_of(extension, src, SyntaxKind::IdentifierAttribute),
+            expected,
+            "{}",
+            extension
+        );
+    }
+}
+
+#[test]
+fn test_embedded_language() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "<p>var</p>\n<script>\nvar x = 1;\n</script>\n";
+    let (document, unhandled_scopes) = generator(&syntax_set, "html", src)
+        .generate_with_report()
+        .unwrap();
+
+    let keywords: Vec<_> = document
+        .occurrences
+        .iter()
+        .filter(|o| o.syntax_kind == SyntaxKind::IdentifierKeyword)
+        .map(|o| o.range.clone())
+        .collect();
+    assert_eq!(keywords, vec![vec![2, 0, 3]]);
+    assert!(!unhandled_scopes
+        .iter()
+        .any(|scope| scope.starts_with("source.") || scope.starts_with("text.")));
+}
+
+#[test]
+fn test_deeply_nested_scopes() {
+    let src = "{\"a\": [{\"b\": [[1, 2], {\"c\": [\"d\", {\"e\": 3}]}]}]}\n";
+    let document = generate("json", src);
+    let occurrences: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|o| (o.range.clone(), o.syntax_kind))
+        .collect();
+
+    let bracket = SyntaxKind::PunctuationBracket;
+    let delimiter = SyntaxKind::PunctuationDelimiter;
+    let key = SyntaxKind::IdentifierAttribute;
+    let number = SyntaxKind::NumericLiteral;
+    let expected = [
+        (0, 1, bracket),
+        (1, 4, key),
+        (4, 5, delimiter),
+        (6, 7, bracket),
+        (7, 8, bracket),
+        (8, 11, key),
+        (11, 12, delimiter),
+        (13, 14, bracket),
+        (14, 15, bracket),
+        (15, 16, number),
+        (16, 17, delimiter),
+        (18, 19, number),
+        (19, 20, bracket),
+        (20, 21, delimiter),
+        (22, 23, bracket),
+        (23, 26, key),
+        (26, 27, delimiter),
+        (28, 29, bracket),
+        (29, 32, SyntaxKind::StringLiteral),
+        (32, 33, delimiter),
+        (34, 35, bracket),
+        (35, 38, key),
+        (38, 39, delimiter),
+        (40, 41, number),
+        // The seven closing brackets, innermost first.
+        (41, 42, bracket),
+        (42, 43, bracket),
+        (43, 44, bracket),
+        (44, 45, bracket),
+        (45, 46, bracket),
+        (46, 47, bracket),
+        (47, 48, bracket),
+    ];
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|&(start, end, kind)| (vec![0, start, end], kind))
+        .collect();
+    assert_eq!(occurrences, expected);
+}
+
+impl<K> OccurrenceSink<K> for Vec<(Vec<i32>, K)> {
+    fn push_highlight(&mut self, _: &PartialHighlight<K>, kind: K, range: Vec<i32>) {
+        self.push((range, kind));
+    }
+}
+
+fn synthetic_hl(row: i32, col: i32, kind: &'static str) -> PartialHighlight<&'static str> {
+    PartialHighlight {
+        row,
+        col,
+        kind: Some(kind),
+        symbol: String::new(),
+        symbol_roles: 0,
+        diagnostic: None,
+        split: false,
+    }
+}
+
+#[test]
+fn test_highlight_manager_splits_nested_highlight() {
+    // "hello, world" with the comma as punctuation.
+    let mut highlight_manager = HighlightManager::default();
+    let mut highlights = Vec::new();
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 0, "string"));
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 6, "punctuation"));
+    highlight_manager.pop_hl(&mut highlights, 0, 7);
+    highlight_manager.pop_hl(&mut highlights, 0, 14);
+
+    assert_eq!(
+        highlights,
+        vec![
+            (vec![0, 0, 0, 6], "string"),
+            (vec![0, 6, 0, 7], "punctuation"),
+            (vec![0, 7, 0, 14], "string"),
+        ]
+    );
+}
+
+#[test]
+fn test_highlight_manager_splits_at_end_of_line() {
+    let mut highlight_manager = HighlightManager::default();
+    let mut highlights = Vec::new();
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 2, "comment"));
+    highlight_manager.flush_end_of_line(&mut highlights, 0, 5);
+    highlight_manager.skip_line(1);
+    highlight_manager.pop_hl(&mut highlights, 2, 3);
+
+    assert_eq!(
+        highlights,
+        vec![(vec![0, 2, 0, 5], "comment"), (vec![2, 0, 2, 3], "comment")]
+    );
+}
+
+#[test]
+fn test_highlight_manager_empty_ranges() {
+    let mut highlight_manager = HighlightManager {
+        emit_empty_ranges: true,
+        ..Default::default()
+    };
+    let mut highlights = Vec::new();
+    // The string is split where the prefix starts, which doesn't make it
+    // empty there.
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 0, "string"));
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 0, "prefix"));
+    highlight_manager.pop_hl(&mut highlights, 0, 1);
+    // The marker ends where it starts, so it is empty.
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 2, "marker"));
+    highlight_manager.pop_hl(&mut highlights, 0, 2);
+    // The rest of the string after the escape is empty, but the string
+    // isn't.
+    highlight_manager.push_hl(&mut highlights, synthetic_hl(0, 2, "escape"));
+    highlight_manager.pop_hl(&mut highlights, 0, 4);
+    highlight_manager.pop_hl(&mut highlights, 0, 4);
+
+    assert_eq!(
+        highlights,
+        vec![
+            (vec![0, 0, 0, 1], "prefix"),
+            (vec![0, 1, 0, 2], "string"),
+            (vec![0, 2, 2], "marker"),
+            (vec![0, 2, 0, 4], "escape"),
+        ]
+    );
+}
+
+#[test]
+fn test_unmapped_scope_does_not_move_enclosing_highlight() {
+    let partial_hl = |col, kind| PartialHighlight {
+        row: 0,
+        col,
+        kind,
+        symbol: String::new(),
+        symbol_roles: 0,
+        diagnostic: None,
+        split: false,
+    };
+
+    // A string with an unmapped scope (like a nested `source`) in front of
+    // a mapped escape: "ab\ncd"
+    let mut highlight_manager = HighlightManager::default();
+    let mut occurrences: Vec<Occurrence> = Vec::new();
+    highlight_manager.push_hl(
+        &mut occurrences,
+        partial_hl(0, Some(SyntaxKind::StringLiteral)),
+    );
+    highlight_manager.push_hl(&mut occurrences, partial_hl(1, None));
+    highlight_manager.pop_hl(&mut occurrences, 0, 2);
+    assert!(occurrences.is_empty());
+    assert_eq!(highlight_manager.highlights[0].col, 0);
+
+    highlight_manager.push_hl(
+        &mut occurrences,
+        partial_hl(3, Some(SyntaxKind::StringLiteralEscape)),
+    );
+    highlight_manager.pop_hl(&mut occurrences, 0, 5);
+    highlight_manager.pop_hl(&mut occurrences, 0, 8);
+
+    assert_eq!(
+        occurrences,
+        vec![
+            new_occurrence(vec![0, 0, 3], SyntaxKind::StringLiteral, 0),
+            new_occurrence(vec![0, 3, 5], SyntaxKind::StringLiteralEscape, 0),
+            new_occurrence(vec![0, 5, 8], SyntaxKind::StringLiteral, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_process_line_matches_generate() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\n/* multi\nline */\nfunc main() {\n\tx := \"s\"\n}\n";
+
+    let mut generator = generator(&syntax_set, "go", "");
+    let mut occurrences = Vec::new();
+    for (row, line) in LinesWithEndings::from(src).enumerate() {
+        occurrences.extend(generator.process_line(row, line).unwrap());
+    }
+    generator.finish().unwrap();
+
+    let document = generate("go", src);
+    assert_eq!(occurrences, document.occurrences.into_vec());
+}
+
+#[test]
+fn test_reports_unhandled_scopes() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nfunc main() {}\n";
+    let (_, unhandled_scopes) = generator(&syntax_set, "go", src)
+        .generate_with_report()
+        .unwrap();
+
+    let mut sorted = unhandled_scopes.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(unhandled_scopes, sorted);
+    assert!(unhandled_scopes.contains(&"meta.block.go".to_string()));
+    assert!(!unhandled_scopes.contains(&"source.go".to_string()));
+}
+
+#[test]
+fn test_template_interpolation() {
+    let kinds = |extension: &str, src: &str| -> Vec<_> {
+        generate(extension, src)
+            .occurrences
+            .iter()
+            .map(|occurrence| (occurrence.range.clone(), occurrence.syntax_kind))
+            .collect()
+    };
+
+    // The expression is outside of the string, so it isn't split into a
+    // string occurrence, and the delimiters are punctuation.
+    assert_eq!(
+        kinds("js", "`a${x}b`\n"),
+        vec![
+            (vec![0, 0, 2], SyntaxKind::StringLiteral),
+            (vec![0, 2, 4], SyntaxKind::PunctuationBracket),
+            (vec![0, 4, 5], SyntaxKind::Identifier),
+            (vec![0, 5, 6], SyntaxKind::PunctuationBracket),
+            (vec![0, 6, 8], SyntaxKind::StringLiteral),
+        ]
+    );
+    // Python names have no scope of their own, so the expression gets no
+    // occurrence, but it still isn't part of the string.
+    assert_eq!(
+        kinds("py", "f\"a{x}b\"\n"),
+        vec![
+            (vec![0, 0, 1], SyntaxKind::StringLiteral),
+            (vec![0, 1, 2], SyntaxKind::StringLiteral),
+            (vec![0, 2, 3], SyntaxKind::StringLiteral),
+            (vec![0, 3, 4], SyntaxKind::PunctuationBracket),
+            (vec![0, 5, 6], SyntaxKind::PunctuationBracket),
+            (vec![0, 6, 8], SyntaxKind::StringLiteral),
+        ]
+    );
+}
+
+#[test]
+fn test_sql() {
+    let src = "SELECT * FROM t WHERE x = 1\n";
+    let document = generate("sql", src);
+
+    let kinds: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|occurrence| (occurrence.range.clone(), occurrence.syntax_kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (vec![0, 0, 6], SyntaxKind::IdentifierKeyword),
+            (vec![0, 7, 8], SyntaxKind::IdentifierOperator),
+            (vec![0, 9, 13], SyntaxKind::IdentifierKeyword),
+            (vec![0, 16, 21], SyntaxKind::IdentifierKeyword),
+            (vec![0, 24, 25], SyntaxKind::IdentifierOperator),
+            (vec![0, 26, 27], SyntaxKind::NumericLiteral),
+        ]
+    );
+}
+
+#[test]
+fn test_markdown_markup() {
+    let src = "# Title\nSome **bold** and *italic* text, [a link](https://example.com).\n";
+    let document = generate("md", src);
+
+    let kind_at = |range: Vec<i32>| {
+        document
+            .occurrences
+            .iter()
+            .find(|occurrence| occurrence.range == range)
+            .map(|occurrence| occurrence.syntax_kind)
+    };
+    assert_eq!(kind_at(vec![0, 2, 7]), Some(SyntaxKind::Tag));
+    assert_eq!(kind_at(vec![1, 5, 13]), Some(SyntaxKind::IdentifierKeyword));
+    assert_eq!(
+        kind_at(vec![1, 18, 26]),
+        Some(SyntaxKind::IdentifierAttribute)
+    );
+    assert_eq!(
+        kind_at(vec![1, 42, 61]),
+        Some(SyntaxKind::StringLiteralSpecial)
+    );
+}
+
+#[test]
+fn test_markdown_fenced_code_block() {
+    let src = "Some prose.\n\n```go\nfunc main() {}\n```\n\nMore func prose.\n";
+    let document = generate("md", src);
+
+    assert!(document.occurrences.contains(&new_occurrence(
+        vec![3, 0, 4],
+        SyntaxKind::IdentifierKeyword,
+        0
+    )));
+    assert!(document
+        .occurrences
+        .iter()
+        .all(|o| o.range[0] != 0 && o.range[0] != 6));
+}
+
+#[test]
+fn test_enabled_kinds() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nfunc main() {\n\tfmt.Println(\"hello\", 1)\n}\n";
+    let document = generator(&syntax_set, "go", src)
+        .with_enabled_kinds([SyntaxKind::StringLiteral].into_iter().collect())
+        .generate()
+        .unwrap();
+
+    assert_eq!(
+        document.occurrences.into_vec(),
+        vec![new_occurrence(
+            vec![2, 13, 20],
+            SyntaxKind::StringLiteral,
+            0
+        )]
+    );
+}
+
+#[test]
+fn test_emit_empty_ranges() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    // The raw string starts with its `r` prefix, but the string scope isn't
+    // empty, so it gets no empty range in front of the prefix.
+    let src = "fn main() { let x = r#\"a\"#; }\n";
+    let generate = |emit_empty_ranges| {
+        generator(&syntax_set, "rs", src)
+            .with_emit_empty_ranges(emit_empty_ranges)
+            .generate()
+            .unwrap()
+            .occurrences
+            .into_vec()
+    };
+
+    let occurrences = generate(false);
+    assert!(occurrences.iter().any(|o| o.range == vec![0, 20, 21]));
+    assert_eq!(generate(true), occurrences);
+}
+
+#[test]
+fn test_single_line_ranges_have_3_elements() {
+    let document = generate("go", "package main\n");
+    assert_eq!(
+        document.occurrences.into_vec(),
+        vec![new_occurrence(
+            vec![0, 0, 7],
+            SyntaxKind::IdentifierKeyword,
+            0
+        )]
+    );
+
+    let occurrence = new_occurrence(vec![0, 5, 2, 3], SyntaxKind::StringLiteral, 0);
+    assert_eq!(occurrence.range, vec![0, 5, 2, 3]);
+    let occurrence = new_occurrence(vec![1, 5, 1, 9], SyntaxKind::StringLiteral, 0);
+    assert_eq!(occurrence.range, vec![1, 5, 9]);
+}
+
+#[test]
+fn test_ignore_scopes() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "some text\n";
+    let scope_mapping: HashMap<String, SyntaxKind> = [("text".to_string(), SyntaxKind::Comment)]
+        .into_iter()
+        .collect();
+
+    let document = generator(&syntax_set, "md", src)
+        .with_scope_mapping(scope_mapping.clone())
+        .with_ignore_scopes(["source".to_string()].into_iter().collect())
+        .generate()
+        .unwrap();
+    assert!(!document.occurrences.is_empty());
+
+    let (document, unhandled_scopes) = generator(&syntax_set, "md", src)
+        .with_scope_mapping(scope_mapping)
+        .with_ignore_scopes(
+            ["source".to_string(), "text".to_string()]
+                .into_iter()
+                .collect(),
+        )
+        .generate_with_report()
+        .unwrap();
+    assert!(document.occurrences.is_empty());
+    assert!(!unhandled_scopes.iter().any(|s| s.starts_with("text")));
+}
+
+#[test]
+fn test_column_encoding() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "x := \"🥳\" + 1\n";
+
+    let numbers = |column_encoding| -> Vec<Vec<i32>> {
+        generator(&syntax_set, "go", src)
+            .with_column_encoding(column_encoding)
+            .generate()
+            .unwrap()
+            .occurrences
+            .iter()
+            .filter(|o| o.syntax_kind == SyntaxKind::NumericLiteral)
+            .map(|o| o.range.clone())
+            .collect()
+    };
+
+    assert_eq!(numbers(ColumnEncoding::Utf8), vec![vec![0, 14, 15]]);
+    assert_eq!(numbers(ColumnEncoding::Utf16), vec![vec![0, 12, 13]]);
+    assert_eq!(numbers(ColumnEncoding::Utf32), vec![vec![0, 11, 12]]);
+}
+
+#[test]
+fn test_generate_with_byte_offsets() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\r\n\nfunc f() {\n\tx := \"\u{1F469}\u{200D}\u{1F52C}\" + 1 // é\n}\n";
+
+    for column_encoding in [
+        ColumnEncoding::Utf8,
+        ColumnEncoding::Utf16,
+        ColumnEncoding::Utf32,
+        ColumnEncoding::ExpandedTabs { tab_width: 4 },
+        ColumnEncoding::GraphemeCluster,
+    ] {
+        let (document, offsets) = generator(&syntax_set, "go", src)
+            .with_column_encoding(column_encoding)
+            .generate_with_byte_offsets()
+            .unwrap();
+        assert_eq!(offsets.len(), document.occurrences.len());
+
+        let text = |kind| {
+            document
+                .occurrences
+                .iter()
+                .zip(&offsets)
+                .filter(|(occurrence, _)| occurrence.syntax_kind == kind)
+                .map(|(_, &(start, end))| &src[start..end])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(text(SyntaxKind::IdentifierKeyword), vec!["package", "func"]);
+        assert_eq!(
+            text(SyntaxKind::StringLiteral),
+            vec!["\"\u{1F469}\u{200D}\u{1F52C}\""]
+        );
+        assert_eq!(text(SyntaxKind::NumericLiteral), vec!["1"]);
+        assert_eq!(text(SyntaxKind::Comment), vec!["// é"]);
+    }
+}
+
+#[test]
+fn test_grapheme_cluster_columns() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    // The woman scientist emoji is a ZWJ sequence of three chars.
+    let src = "x := \"\u{1F469}\u{200D}\u{1F52C}\" + 1\n";
+
+    let ranges = |column_encoding| -> Vec<(Vec<i32>, SyntaxKind)> {
+        generator(&syntax_set, "go", src)
+            .with_column_encoding(column_encoding)
+            .generate()
+            .unwrap()
+            .occurrences
+            .iter()
+            .filter(|o| {
+                o.syntax_kind == SyntaxKind::StringLiteral
+                    || o.syntax_kind == SyntaxKind::NumericLiteral
+            })
+            .map(|o| (o.range.clone(), o.syntax_kind))
+            .collect()
+    };
+
+    assert_eq!(
+        ranges(ColumnEncoding::GraphemeCluster),
+        vec![
+            (vec![0, 5, 8], SyntaxKind::StringLiteral),
+            (vec![0, 11, 12], SyntaxKind::NumericLiteral),
+        ]
+    );
+    assert_eq!(
+        ranges(ColumnEncoding::Utf32),
+        vec![
+            (vec![0, 5, 10], SyntaxKind::StringLiteral),
+            (vec![0, 13, 14], SyntaxKind::NumericLiteral),
+        ]
+    );
+}
+
+#[test]
+fn test_line_columns_snap_to_grapheme_clusters() {
+    let line = "a\u{1F469}\u{200D}\u{1F52C}b\n";
+    let mut columns = LineColumns::new(line, ColumnEncoding::GraphemeCluster);
+    assert_eq!(columns.column(1), 1);
+    // The offset of the ZWJ, which is inside of the cluster.
+    assert_eq!(columns.column(5), 1);
+    assert_eq!(columns.column(12), 2);
+}
+
+#[test]
+fn test_end_of_line_excludes_newline() {
+    let last_range = |src: &str| {
+        let document = generate("go", src);
+        document.occurrences.last().unwrap().range.clone()
+    };
+
+    // The comment is popped after the newline, at the very end of the
+    // line, but ends where the text does.
+    let src = "package main // hi";
+    assert_eq!(last_range(src), vec![0, 13, 18]);
+    assert_eq!(last_range(&format!("{}\n", src)), vec![0, 13, 18]);
+    assert_eq!(last_range(&format!("{}\r\n", src)), vec![0, 13, 18]);
+}
+
+#[test]
+fn test_blank_line_inside_of_string() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let strings = |src: &str, emit_empty_ranges| -> Vec<_> {
+        generator(&syntax_set, "go", src)
+            .with_emit_empty_ranges(emit_empty_ranges)
+            .generate()
+            .unwrap()
+            .occurrences
+            .iter()
+            .filter(|occurrence| occurrence.syntax_kind == SyntaxKind::StringLiteral)
+            .map(|occurrence| occurrence.range.clone())
+            .collect()
+    };
+
+    // The blank line has no columns, not even for its line ending, so
+    // the string has nothing to highlight on it. The string isn't empty, so
+    // it gets no empty range there either.
+    let src = "var s = `a\n\nb`\n";
+    for src in [src.to_string(), src.replace('\n', "\r\n")] {
+        assert_eq!(strings(&src, false), vec![vec![0, 8, 10], vec![2, 0, 2]]);
+        assert_eq!(strings(&src, true), strings(&src, false));
+    }
+}
+
+#[test]
+fn test_crlf_line_endings() {
+    let src = "package main // a\n/* b\nc */\nvar s = \"d\"\n";
+    let lf = generate("go", src);
+    let crlf = generate("go", &src.replace('\n', "\r\n"));
+
+    assert!(lf.occurrences.iter().any(|o| o.range == vec![1, 0, 4]));
+    assert_eq!(crlf.occurrences, lf.occurrences);
+}
+
+#[test]
+fn test_generate_with_colors() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let theme_set = syntect::highlighting::ThemeSet::load_defaults();
+    let theme = &theme_set.themes["InspiredGitHub"];
+    let src = "package main\nfunc main() {\n\tx := \"s\"\n}\n";
+
+    let (document, colors) = generator(&syntax_set, "go", src)
+        .generate_with_colors(theme)
+        .unwrap();
+    assert_eq!(document.occurrences, generate("go", src).occurrences);
+    assert_eq!(colors.len(), document.occurrences.len());
+
+    let colors: HashMap<usize, String> = colors.into_iter().collect();
+    let keyword = document
+        .occurrences
+        .iter()
+        .position(|o| o.syntax_kind == SyntaxKind::IdentifierKeyword)
+        .unwrap();
+    let string = document
+        .occurrences
+        .iter()
+        .position(|o| o.syntax_kind == SyntaxKind::StringLiteral)
+        .unwrap();
+    assert_eq!(colors[&keyword].len(), 7);
+    assert!(colors[&keyword].starts_with('#'));
+    assert_ne!(colors[&keyword], colors[&string]);
+}
+
+#[test]
+fn test_generate_scope_dump() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let scopes = |src| {
+        generator(&syntax_set, "go", src)
+            .generate_scope_dump()
+            .unwrap()
+    };
+
+    assert_eq!(
+        scopes("package main\n"),
+        vec![
+            (vec![0, 0, 7], "keyword.control.go".to_string()),
+            (vec![0, 0, 12], "source.go".to_string()),
+        ]
+    );
+
+    let dump = scopes("package main\n/* a\nb */\n");
+    assert!(dump.contains(&(vec![1, 0, 2, 4], "comment.block.go".to_string())));
+}
+
+#[test]
+fn test_load_scope_mapping() {
+    let scope_mapping =
+        load_scope_mapping(std::path::Path::new("./testdata/scope_mapping.json")).unwrap();
+    assert_eq!(scope_mapping.len(), 3);
+
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main // hi\nvar x = 1\n";
+    let document = generator(&syntax_set, "go", src)
+        .with_scope_mapping(scope_mapping)
+        .generate()
+        .unwrap();
+    let kinds: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|o| (o.range.clone(), o.syntax_kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (vec![0, 0, 7], SyntaxKind::IdentifierKeyword),
+            (vec![0, 13, 18], SyntaxKind::Comment),
+            // `var` is a storage.type, which is not in the mapping, but
+            // `=` is a keyword.operator.
+            (vec![1, 6, 7], SyntaxKind::IdentifierKeyword),
+            (vec![1, 8, 9], SyntaxKind::NumericLiteral),
+        ]
+    );
+
+    match parse_scope_mapping(r#"{"keyword": "Keyword"}"#) {
+        Err(ScopeMappingError::UnknownKind { scope, kind }) => {
+            assert_eq!((scope.as_str(), kind.as_str()), ("keyword", "Keyword"))
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(matches!(
+        parse_scope_mapping("[]"),
+        Err(ScopeMappingError::Json(_))
+    ));
+}
+
+#[test]
+fn test_labels() {
+    let src = "func f() {\nouter:\n\tfor {\n\t\tbreak outer\n\t}\n}\n";
+    let document = generate("go", src);
+    let occurrence_at = |range: Vec<i32>| {
+        document
+            .occurrences
+            .iter()
+            .find(|occurrence| occurrence.range == range)
+            .unwrap()
+    };
+
+    let label = occurrence_at(vec![1, 0, 5]);
+    assert_eq!(label.syntax_kind, SyntaxKind::Identifier);
+    assert_eq!(label.symbol_roles, SymbolRole::Definition as i32);
+    assert_eq!(
+        occurrence_at(vec![3, 2, 7]).syntax_kind,
+        SyntaxKind::IdentifierKeyword
+    );
+}
+
+#[test]
+fn test_type_parameters() {
+    let src = "class Box<T> {\n  <U> U map(T t) { return null; }\n}\n";
+    assert_eq!(
+        occurrences_of("java", src, SyntaxKind::IdentifierType),
+        vec![vec![0, 6, 9], vec![0, 10, 11], vec![1, 3, 4]]
+    );
+
+    // The Go and Rust syntaxes don't scope type parameters, so they get no
+    // occurrence at all.
+    let covers = |extension, src, character| {
+        generate(extension, src)
+            .occurrences
+            .iter()
+            .any(|o| o.range[1] <= character && character < o.range[2])
+    };
+    assert!(!covers("go", "func Foo[T any]() {}\n", 9));
+    assert!(!covers("rs", "fn foo<T: Clone>() {}\n", 7));
+}
+
+#[test]
+fn test_preprocessor_directives() {
+    let macros = |extension, src| occurrences_of(extension, src, SyntaxKind::IdentifierMacro);
+
+    let src = "#include <stdio.h>\n#define MAX 10\n#ifdef MAX\n#endif\n";
+    let directives = vec![vec![0, 0, 8], vec![1, 0, 7], vec![2, 0, 6], vec![3, 0, 6]];
+    assert_eq!(macros("c", src), directives);
+    assert_eq!(macros("cpp", src), directives);
+    // C# scopes the `#` separately from the directive.
+    assert_eq!(
+        macros("cs", "#if DEBUG\n#endif\n"),
+        vec![vec![0, 0, 1], vec![0, 1, 3], vec![1, 0, 1], vec![1, 1, 6]]
+    );
+
+    // Imports in other languages are still keywords.
+    assert!(macros("py", "import os\nfrom a import b\n").is_empty());
+}
+
+#[test]
+fn test_namespaces() {
+    let occurrences = |extension, src| generate(extension, src).occurrences.into_vec();
+
+    assert!(
+        occurrences("cpp", "namespace foo {}\n").contains(&new_occurrence(
+            vec![0, 10, 13],
+            SyntaxKind::IdentifierModule,
+            SymbolRole::Definition as i32
+        ))
+    );
+    assert!(
+        occurrences("php", "<?php\nuse Foo\\Bar;\n").contains(&new_occurrence(
+            vec![1, 4, 7],
+            SyntaxKind::IdentifierModule,
+            0
+        ))
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "let x = 1;\n)\n";
+
+    let document = generate("js", src);
+    assert!(document
+        .occurrences
+        .iter()
+        .all(|o| o.diagnostics.is_empty()));
+
+    let (document, unhandled_scopes) = generator(&syntax_set, "js", src)
+        .with_diagnostics(true)
+        .generate_with_report()
+        .unwrap();
+    let invalid: Vec<_> = document
+        .occurrences
+        .iter()
+        .filter(|o| !o.diagnostics.is_empty())
+        .collect();
+    assert_eq!(invalid.len(), 1);
+    assert_eq!(invalid[0].range, vec![1, 0, 1]);
+    assert_eq!(invalid[0].diagnostics[0].severity, Severity::Warning);
+    assert_eq!(
+        invalid[0].diagnostics[0].code,
+        "invalid.illegal.stray-bracket-end.js"
+    );
+    assert!(!unhandled_scopes.iter().any(|s| s.starts_with("invalid")));
+}
+
+#[test]
+fn test_properties_files() {
+    let src = "# comment\n! other comment\nkey=value\nname : John Doe\n";
+    insta::assert_snapshot!(dump_document(&generate("properties", src), src));
+}
+
+#[test]
+fn test_json_keys() {
+    let src = "{\"a\": \"b\"}\n";
+    insta::assert_snapshot!(dump_document(&generate("json", src), src));
+}
+
+#[test]
+fn test_coalescing() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nfunc main() {}\n";
+    let brackets = |coalescing| {
+        generator(&syntax_set, "go", src)
+            .with_coalescing(coalescing)
+            .generate()
+            .unwrap()
+            .occurrences
+            .into_iter()
+            .filter(|o| o.syntax_kind == SyntaxKind::PunctuationBracket)
+            .map(|o| o.range)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        brackets(false),
+        vec![
+            vec![1, 9, 10],
+            vec![1, 10, 11],
+            vec![1, 12, 13],
+            vec![1, 13, 14]
+        ]
+    );
+    assert_eq!(brackets(true), vec![vec![1, 9, 11], vec![1, 12, 14]]);
+}
+
+#[test]
+fn test_constants() {
+    let constants = |extension, src| {
+        generate(extension, src)
+            .occurrences
+            .into_iter()
+            .filter(|o| {
+                matches!(
+                    o.syntax_kind,
+                    SyntaxKind::IdentifierConstant | SyntaxKind::Identifier
+                )
+            })
+            .map(|o| (o.range, o.syntax_kind))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        constants("rs", "const FOO: u32 = 1;\n"),
+        vec![(vec![0, 6, 9], SyntaxKind::IdentifierConstant)]
+    );
+    assert_eq!(
+        constants("py", "FOO = 1\nfoo = FOO\n"),
+        vec![
+            (vec![0, 0, 3], SyntaxKind::IdentifierConstant),
+            (vec![1, 6, 9], SyntaxKind::IdentifierConstant),
+        ]
+    );
+    assert_eq!(
+        constants("go", "var foo = 1\n"),
+        vec![(vec![0, 4, 7], SyntaxKind::Identifier)]
+    );
+}
+
+#[test]
+fn test_merge_documents() {
+    let first = "package main\nvar x = 1\n";
+    let second = "var y = \"two\"\nvar z = 3\n";
+
+    let merged = merge_documents(vec![
+        (0, generate("go", first)),
+        (2, generate("go", second)),
+    ])
+    .unwrap();
+    assert_eq!(
+        merged.occurrences,
+        generate("go", &format!("{}{}", first, second)).occurrences
+    );
+    assert!(merged.occurrences.contains(&new_occurrence(
+        vec![3, 8, 9],
+        SyntaxKind::NumericLiteral,
+        0
+    )));
+
+    let multi_line = Document {
+        occurrences: vec![new_occurrence(vec![0, 2, 1, 3], SyntaxKind::Comment, 0)].into(),
+        ..Default::default()
+    };
+    let merged = merge_documents(vec![(5, multi_line.clone())]).unwrap();
+    assert_eq!(merged.occurrences[0].range, vec![5, 2, 6, 3]);
+
+    // The end of the comment is one row past i32::MAX.
+    assert_eq!(
+        merge_documents(vec![(i32::MAX as usize, multi_line)]),
+        Err(HighlightError::PositionOverflow {
+            row: i32::MAX as usize + 1
+        })
+    );
+}
+
+#[test]
+fn test_multi_line_raw_string() {
+    let src = "var s = `a\n\tbb\nccc`\nvar t = 1\n";
+    assert_eq!(
+        occurrences_of("go", src, SyntaxKind::StringLiteral),
+        vec![vec![0, 8, 10], vec![1, 0, 3], vec![2, 0, 4]]
+    );
+    assert!(occurrences_of("go", src, SyntaxKind::IdentifierKeyword).contains(&vec![3, 0, 3]));
+}
+
+#[test]
+fn test_no_trailing_newline() {
+    for src in [
+        "package main",
+        "package main\nvar x = 1",
+        "package main\n// comment",
+        "package main\nvar s = \"str\"",
+    ] {
+        let without_newline = generate("go", src);
+        let with_newline = generate("go", &format!("{}\n", src));
+        assert_eq!(
+            without_newline.occurrences, with_newline.occurrences,
+            "{:?}",
+            src
+        );
+    }
+}
+
+#[test]
+fn test_tab_width() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "func main() {\n\tx := 1\n}\n";
+
+    let numbers = |generator: DocumentGenerator| -> Vec<Vec<i32>> {
+        generator
+            .generate()
+            .unwrap()
+            .occurrences
+            .iter()
+            .filter(|o| o.syntax_kind == SyntaxKind::NumericLiteral)
+            .map(|o| o.range.clone())
+            .collect()
+    };
+
+    assert_eq!(
+        numbers(generator(&syntax_set, "go", src)),
+        vec![vec![1, 6, 7]]
+    );
+    assert_eq!(
+        numbers(generator(&syntax_set, "go", src).with_tab_width(4)),
+        vec![vec![1, 9, 10]]
+    );
+
+    // A tab after other indentation only advances to the next tab stop.
+    let src = "func main() {\n  \tx := 1\n}\n";
+    assert_eq!(
+        numbers(generator(&syntax_set, "go", src).with_tab_width(4)),
+        vec![vec![1, 9, 10]]
+    );
+}
+
+#[test]
+fn test_tab_stops() {
+    let line = "\t foo\tbar  \tbaz\n";
+    let mut columns = LineColumns::new(line, ColumnEncoding::ExpandedTabs { tab_width: 4 });
+    assert_eq!(columns.column(line.find("foo").unwrap()), 5);
+    assert_eq!(columns.column(line.find("bar").unwrap()), 12);
+    assert_eq!(columns.column(line.find("baz").unwrap()), 20);
+    assert_eq!(columns.column(line.len()), 23);
+}
+
+#[test]
+fn test_column_inside_multibyte_character() {
+    // 'é' is two bytes, so byte offset 2 is in the middle of it.
+    let line = "aé b\n";
+    let mut columns = LineColumns::new(line, ColumnEncoding::Utf32);
+    assert_eq!(columns.column(0), 0);
+    assert_eq!(columns.column(2), 1);
+    assert_eq!(columns.column(3), 2);
+    assert_eq!(columns.column(4), 3);
+    assert_eq!(columns.column(line.len()), 4);
+}
+
+#[test]
+fn test_line_range() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\n/*\n1\n*/\nvar x = 2\nvar y = 3\n";
+    let document = generator(&syntax_set, "go", src)
+        .with_line_range(2, 5)
+        .generate()
+        .unwrap();
+
+    let occurrences: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|o| (o.range.clone(), o.syntax_kind))
+        .collect();
+    assert_eq!(
+        occurrences,
+        vec![
+            // Row 2 is only a comment because the comment started on row 1.
+            (vec![2, 0, 1], SyntaxKind::Comment),
+            (vec![3, 0, 2], SyntaxKind::Comment),
+            (vec![4, 0, 3], SyntaxKind::IdentifierKeyword),
+            (vec![4, 4, 5], SyntaxKind::Identifier),
+            (vec![4, 6, 7], SyntaxKind::IdentifierOperator),
+            (vec![4, 8, 9], SyntaxKind::NumericLiteral),
+        ]
+    );
+}
+
+#[test]
+fn test_definition_role() {
+    let src = "package main\nfunc main() {\n\tbar()\n}\n";
+    let document = generate("go", src);
+
+    let functions: Vec<_> = document
+        .occurrences
+        .iter()
+        .filter(|o| o.syntax_kind == SyntaxKind::IdentifierFunction)
+        .map(|o| (o.range.clone(), o.symbol_roles))
+        .collect();
+    assert_eq!(
+        functions,
+        vec![
+            (vec![1, 5, 9], SymbolRole::Definition as i32),
+            (vec![2, 1, 4], 0),
+        ]
+    );
+}
+
+#[test]
+fn test_local_symbols() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\ntype Foo struct {}\nfunc main() {\n\tbar()\n}\nfunc bar() {}\n";
+    let document = generator(&syntax_set, "go", src)
+        .with_local_symbols(true)
+        .generate()
+        .unwrap();
+
+    let symbols: Vec<_> = document
+        .occurrences
+        .iter()
+        .filter(|o| !o.symbol.is_empty())
+        .map(|o| (o.range.clone(), o.symbol.as_str(), o.symbol_roles))
+        .collect();
+    let definition = SymbolRole::Definition as i32;
+    assert_eq!(
+        symbols,
+        vec![
+            (vec![1, 5, 8], "local 0", definition),
+            (vec![2, 5, 9], "local 1", definition),
+            (vec![5, 5, 8], "local 2", definition),
+        ]
+    );
+
+    let document = generate("go", src);
+    assert!(document.occurrences.iter().all(|o| o.symbol.is_empty()));
+}
+
+#[test]
+fn test_for_query_falls_back_to_plain_text() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let query = SourcegraphQuery {
+        filetype: Some("totally-unknown".to_string()),
+        fallback_plain_text: true,
+        code: "fn main() {}\n".to_string(),
+        ..Default::default()
+    };
+
+    let document = DocumentGenerator::for_query(&syntax_set, &query)
+        .unwrap()
+        .generate()
+        .unwrap();
+    assert!(document.occurrences.is_empty());
+}
+
+#[test]
+fn test_plain_text_is_not_parsed() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let query = SourcegraphQuery {
+        filetype: Some("text".to_string()),
+        code: "some \"plain\" text // 42\n".repeat(10_000),
+        ..Default::default()
+    };
+
+    let mut generator = DocumentGenerator::for_query(&syntax_set, &query).unwrap();
+    let document = generator.generate_ref().unwrap();
+    assert!(document.occurrences.is_empty());
+    // Parsing would have resolved the text.plain scope.
+    assert!(generator.scope_kinds.is_empty());
+}
+
+#[test]
+fn test_engine_highlights_multiple_queries() {
+    let engine = HighlightEngine::new();
+    let query = |filepath: &str, code: &str| SourcegraphQuery {
+        filepath: filepath.to_string(),
+        code: code.to_string(),
+        ..Default::default()
+    };
+
+    let go = engine.highlight(&query("main.go", "// go\n")).unwrap();
+    let js = engine.highlight(&query("main.js", "/* js */\n")).unwrap();
+
+    assert_eq!(go.occurrences[0].range, vec![0, 0, 5]);
+    assert_eq!(js.occurrences[0].range, vec![0, 0, 8]);
+    assert_eq!(
+        engine.highlight(&query("", "")).err(),
+        Some(HighlightError::Language(
+            LanguageError::NoMatchingLanguage {
+                filepath: "".to_string(),
+                extension: "".to_string(),
+            }
+        ))
+    );
+}
+
+#[test]
+fn test_for_query_without_matching_language() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let query = SourcegraphQuery {
+        extension: "nope".to_string(),
+        code: "nope\n".to_string(),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        DocumentGenerator::for_query(&syntax_set, &query).err(),
+        Some(LanguageError::NoMatchingLanguage {
+            filepath: "".to_string(),
+            extension: "nope".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_depth_profile() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "func f() {\n\tif x {\n\t\ty()\n\t}\n}\n";
+    let (_, stats) = generator(&syntax_set, "go", src)
+        .with_depth_profile(true)
+        .generate_with_stats()
+        .unwrap();
+    // The lowest depth on a line follows the nesting of the braces around
+    // it, while the highest also counts the scopes of its tokens.
+    assert_eq!(
+        stats.depth_profile,
+        vec![(0, 4), (3, 5), (4, 8), (3, 5), (1, 4)]
+    );
+}
+
+#[test]
+fn test_suspicious_lines() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\n/* a\nb */\nvar x = 1\n";
+
+    let (_, stats) = generator(&syntax_set, "go", src)
+        .generate_with_stats()
+        .unwrap();
+    assert!(stats.suspicious_lines.is_empty());
+
+    // Drop the open highlights, as if syntect had never pushed their
+    // scopes, so that the comment is popped on line 2 without one.
+    let mut generator = generator(&syntax_set, "go", "");
+    for (row, line) in LinesWithEndings::from(src).enumerate() {
+        if row == 2 {
+            generator.highlight_manager.highlights.clear();
+        }
+        generator.process_line(row, line).unwrap();
+    }
+    assert_eq!(generator.suspicious_lines, vec![2]);
+}
+
+#[test]
+fn test_generate_with_stats() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = include_str!("snapshots/syntect_files/go_functions.go");
+    let (document, stats) = generator(&syntax_set, "go", src)
+        .generate_with_stats()
+        .unwrap();
+    let (_, unhandled_scopes) = generator(&syntax_set, "go", src)
+        .generate_with_report()
+        .unwrap();
+
+    assert_eq!(stats.lines, 10);
+    assert_eq!(stats.occurrences, document.occurrences.len());
+    assert_eq!(stats.occurrences, generate("go", src).occurrences.len());
+    assert_eq!(stats.unhandled_scopes, unhandled_scopes.len());
+}
+
+#[test]
+fn test_max_lines() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let syntax_reference = syntax_set.find_syntax_by_extension("go").unwrap();
+    let src = "package main\nvar x = 1\nvar y = 2\n";
+    let limits = LimitConfig {
+        max_lines: Some(2),
+        ..Default::default()
+    };
+
+    let (document, stats) = DocumentGenerator::new(&syntax_set, syntax_reference, src, limits)
+        .generate_with_stats()
+        .unwrap();
+    assert!(stats.truncated);
+    assert_eq!(stats.lines, 2);
+    assert!(document.occurrences.iter().all(|o| o.range[0] < 2));
+
+    let (_, stats) = DocumentGenerator::new(&syntax_set, syntax_reference, src, limits)
+        .with_line_range(0, 2)
+        .generate_with_stats()
+        .unwrap();
+    assert!(!stats.truncated);
+}
+
+#[test]
+fn test_max_total_bytes() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let syntax_reference = syntax_set.find_syntax_by_extension("go").unwrap();
+    let src = "package main\nvar x = 1\nvar y = 2\n";
+
+    // The second line ends at byte 23, so it still fits.
+    let limits = LimitConfig {
+        max_total_bytes: Some(23),
+        ..Default::default()
+    };
+    let (document, stats) = DocumentGenerator::new(&syntax_set, syntax_reference, src, limits)
+        .generate_with_stats()
+        .unwrap();
+    assert!(stats.truncated);
+    assert_eq!(stats.lines, 2);
+    assert_eq!(
+        document.occurrences,
+        generate("go", "package main\nvar x = 1\n").occurrences
+    );
+
+    let limits = LimitConfig {
+        max_total_bytes: Some(src.len()),
+        ..Default::default()
+    };
+    let (_, stats) = DocumentGenerator::new(&syntax_set, syntax_reference, src, limits)
+        .generate_with_stats()
+        .unwrap();
+    assert!(!stats.truncated);
+}
+
+#[test]
+fn test_indent_guides() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let indentation = |extension, src| {
+        let document = generator(&syntax_set, extension, src)
+            .with_tab_width(4)
+            .with_indent_guides(true)
+            .generate()
+            .unwrap();
+        document
+            .occurrences
+            .into_iter()
+            .filter(|occ| occ.syntax_kind == SyntaxKind::UnspecifiedSyntaxKind)
+            .map(|occ| occ.range)
+            .collect::<Vec<_>>()
+    };
+
+    let src = "def f(x):\n    if x:\n\treturn \"\"\"a\n    b\"\"\"\n\n    return 1\n";
+    assert_eq!(
+        indentation("py", src),
+        vec![vec![1, 0, 4], vec![2, 0, 4], vec![5, 0, 4]]
+    );
+    assert!(indentation("go", "package main\n\nfunc f() {\n    f()\n}\n").is_empty());
+}
+
+#[test]
+fn test_occurrences_iterator() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\n/* a\nb */\nfunc main() {\n\tx := \"y\" + 1\n}\n";
+
+    let occurrences: Vec<_> = generator(&syntax_set, "go", src)
+        .occurrences()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert!(!occurrences.is_empty());
+    assert_eq!(
+        occurrences,
+        generator(&syntax_set, "go", src)
+            .generate()
+            .unwrap()
+            .occurrences
+            .into_vec()
+    );
+
+    // The limits and line range apply in the same way.
+    let occurrences: Vec<_> = generator(&syntax_set, "go", src)
+        .with_line_range(1, 5)
+        .with_max_occurrences(4)
+        .occurrences()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let document = generator(&syntax_set, "go", src)
+        .with_line_range(1, 5)
+        .with_max_occurrences(4)
+        .generate()
+        .unwrap();
+    assert_eq!(occurrences.len(), 4);
+    assert_eq!(occurrences, document.occurrences.into_vec());
+}
+
+#[test]
+fn test_position_overflow() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let wide_tabs = |src| generator(&syntax_set, "go", src).with_tab_width(i32::MAX as usize);
+
+    // A single tab fits, but anything after it is past i32::MAX.
+    assert!(wide_tabs("package main\n\t\n").generate().is_ok());
+    assert_eq!(
+        wide_tabs("package main\nvar\tx = 1\n").generate(),
+        Err(HighlightError::PositionOverflow { row: 1 })
+    );
+
+    // The iterator yields the occurrences before the line that overflows,
+    // then the error.
+    let mut expected: Vec<_> = wide_tabs("package main\n")
+        .generate()
+        .unwrap()
+        .occurrences
+        .into_iter()
+        .map(Ok)
+        .collect();
+    expected.push(Err(HighlightError::PositionOverflow { row: 1 }));
+    assert_eq!(
+        wide_tabs("package main\nvar\tx = 1\nvar y = 2\n")
+            .occurrences()
+            .collect::<Vec<_>>(),
+        expected
+    );
+
+    // The same check applies to callers that pass the lines themselves.
+    let mut line_by_line = wide_tabs("");
+    assert!(line_by_line.process_line(0, "package main\n").is_ok());
+    assert_eq!(
+        line_by_line.process_line(1, "var\tx = 1\n"),
+        Err(HighlightError::PositionOverflow { row: 1 })
+    );
+    assert_eq!(
+        wide_tabs("var\tx = 1\n").generate_scope_dump(),
+        Err(HighlightError::PositionOverflow { row: 0 })
+    );
+}
+
+#[test]
+fn test_reparse() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let lines = [
+        "package main\n",
+        "\n",
+        "func main() {\n",
+        "\tx := 1\n",
+        "\ty := \"a\"\n",
+        "}\n",
+    ];
+    let src = lines.concat();
+    let mut generator = generator(&syntax_set, "go", &src);
+    let mut document = generator.generate_ref().unwrap();
+
+    let mut lines = lines.to_vec();
+    for (edited_line, new_line_text) in [
+        // Only changes its own line.
+        (3, "\tx := 2 + 3\n"),
+        // Comments out the rest of the file, and then ends the comment.
+        (3, "\t/* x := 1\n"),
+        (4, "\ty := \"a\" */\n"),
+        (1, "// a comment\n"),
+    ] {
+        document = generator
+            .reparse(&document, edited_line, new_line_text)
+            .unwrap();
+        lines[edited_line] = new_line_text;
+        assert_eq!(document, generate("go", &lines.concat()));
+    }
+}
+
+#[test]
+fn test_resume_from_checkpoint() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let syntax_reference = syntax_set.find_syntax_by_extension("rs").unwrap();
+    let src = std::fs::read_to_string("testdata/medium.rs").unwrap();
+
+    let mut generator = generator(&syntax_set, "rs", &src).with_checkpoints(true);
+    let document = generator.generate_ref().unwrap();
+    let checkpoints = generator.checkpoints();
+    assert_eq!(checkpoints.len(), src.lines().count());
+
+    let checkpoint = &checkpoints[checkpoints.len() / 2];
+    let tail = DocumentGenerator::resume(
+        &syntax_set,
+        syntax_reference,
+        &src,
+        LimitConfig::default(),
+        checkpoint,
+    )
+    .generate()
+    .unwrap();
+    let expected: Vec<_> = document
+        .occurrences
+        .into_iter()
+        .filter(|occurrence| occurrence.range[0] as usize >= checkpoint.row())
+        .collect();
+    assert!(!expected.is_empty());
+    assert_eq!(tail.occurrences.into_vec(), expected);
+}
+
+#[test]
+fn test_outline_mode() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = r#"package main
+
+// Point is a point.
+type Point struct {
+	X int
+}
+
+func (p Point) Norm() int { return p.X * 2 }
+
+func main() {
+	fmt.Println("hello")
+}
+"#;
+    let document = generator(&syntax_set, "go", src)
+        .with_outline_mode(true)
+        .generate()
+        .unwrap();
+
+    let ranges: Vec<_> = document
+        .occurrences
+        .iter()
+        .map(|occurrence| occurrence.range.clone())
+        .collect();
+    assert_eq!(ranges, vec![vec![3, 5, 10], vec![7, 15, 19], vec![9, 5, 9]]);
+}
+
+#[test]
+fn test_reset() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    // The first file ends inside of a comment, which must not leak into
+    // the second one.
+    let first = "package main\nfunc main() {}\n/* unterminated\n";
+    let second = "package other\nvar x = \"y\"\n";
+
+    let mut reused = generator(&syntax_set, "go", first).with_local_symbols(true);
+    let fresh = |code| {
+        generator(&syntax_set, "go", code)
+            .with_local_symbols(true)
+            .generate()
+            .unwrap()
+    };
+
+    assert_eq!(reused.generate_ref().unwrap(), fresh(first));
+    assert_eq!(reused.generate_ref().unwrap(), fresh(first));
+    reused.reset(second);
+    assert_eq!(reused.generate_ref().unwrap(), fresh(second));
+}
+
+#[test]
+fn test_reset_reports_unhandled_scopes() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let code = "package main\nfunc main() {}\n";
+    let (_, expected) = generator(&syntax_set, "go", code)
+        .generate_with_report()
+        .unwrap();
+    assert!(expected.contains(&"meta.block.go".to_string()));
+
+    // The second run resolves every scope from the cache, but still
+    // reports the unhandled ones.
+    let mut reused = generator(&syntax_set, "go", code);
+    reused.generate_ref().unwrap();
+    reused.generate_ref().unwrap();
+    reused.reset(code);
+    let (_, unhandled_scopes) = reused.generate_with_report().unwrap();
+    assert_eq!(unhandled_scopes, expected);
+}
+
+#[test]
+fn test_generate_with_timeout() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = std::fs::read_to_string("testdata/medium.rs").unwrap();
+
+    match generator(&syntax_set, "rs", &src).generate_with_timeout(Duration::from_secs(0)) {
+        Err(HighlightError::Timeout { partial, .. }) => {
+            assert!(partial.occurrences.is_empty())
+        }
+        result => panic!("expected a timeout, got {:?}", result.map(|_| ())),
+    }
+    assert_eq!(
+        generator(&syntax_set, "rs", &src).generate_with_timeout(Duration::from_secs(60)),
+        generator(&syntax_set, "rs", &src).generate()
+    );
+}
+
+#[test]
+fn test_generate_with_timeout_partial() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    // Large enough that it can't be highlighted within the timeout, which
+    // is checked before every line.
+    let src = std::fs::read_to_string("testdata/medium.rs")
+        .unwrap()
+        .repeat(100);
+
+    let partial =
+        match generator(&syntax_set, "rs", &src).generate_with_timeout(Duration::from_millis(10)) {
+            Err(HighlightError::Timeout { partial, .. }) => partial.occurrences.into_vec(),
+            result => panic!("expected a timeout, got {:?}", result.map(|_| ())),
+        };
+    let all = generate("rs", &src).occurrences.into_vec();
+    assert!(!partial.is_empty());
+    assert!(partial.len() < all.len());
+    assert_eq!(partial, all[..partial.len()].to_vec());
+}
+
+#[test]
+fn test_styled_runs() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let runs = |src| generator(&syntax_set, "go", src).styled_runs().unwrap();
+
+    assert_eq!(
+        runs("package main"),
+        vec![
+            ("package".to_string(), Some(SyntaxKind::IdentifierKeyword)),
+            (" main".to_string(), None),
+        ]
+    );
+
+    let src = include_str!("snapshots/syntect_files/go_functions.go");
+    let text: String = runs(src).into_iter().map(|(text, _)| text).collect();
+    assert_eq!(text, src);
+}
+
+#[test]
+fn test_generate_filtered() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\n/*\n1\n*/\nvar x = 2\nvar y = 3\n";
+
+    let document = generator(&syntax_set, "go", src)
+        .generate_filtered(|row| row % 2 == 0)
+        .unwrap();
+    let mut expected = generate("go", src);
+    expected
+        .occurrences
+        .retain(|occurrence| occurrence.range[0] % 2 == 0);
+    assert!(!document.occurrences.is_empty());
+    assert_eq!(document, expected);
+}
+
+#[test]
+fn test_generate_cancellable() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nvar x = 1\nvar y = 2\n";
+
+    let cancelled = AtomicBool::new(false);
+    let document = generator(&syntax_set, "go", src)
+        .generate_cancellable(&cancelled)
+        .unwrap();
+    assert_eq!(document, generate("go", src));
+
+    // Cancel once the first two lines are done.
+    let mut lines = 0;
+    let (document, stats) = generator(&syntax_set, "go", src)
+        .generate_document_until(|| {
+            lines += 1;
+            if lines > 2 {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+            cancelled.load(Ordering::Relaxed)
+        })
+        .unwrap();
+    assert!(stats.truncated);
+    assert_eq!(
+        document.occurrences,
+        generate("go", "package main\nvar x = 1\n").occurrences
+    );
+
+    let document = generator(&syntax_set, "go", src)
+        .generate_cancellable(&cancelled)
+        .unwrap();
+    assert!(document.occurrences.is_empty());
+}
+
+#[test]
+fn test_max_occurrences() {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    let src = "package main\nvar x = []int{1, 2, 3, 4, 5}\nvar y = 6\n";
+    let limited = |max_occurrences| {
+        generator(&syntax_set, "go", src)
+            .with_max_occurrences(max_occurrences)
+            .generate_with_stats()
+            .unwrap()
+    };
+    let all = generate("go", src).occurrences.into_vec();
+    let first_line = all.iter().filter(|o| o.range[0] == 0).count();
+
+    let (document, stats) = limited(3);
+    assert!(stats.truncated);
+    assert_eq!(document.occurrences.into_vec(), all[..3].to_vec());
+
+    // The limit is reached at the end of the first line, so the other lines
+    // are dropped as a whole.
+    let (document, stats) = limited(first_line);
+    assert!(stats.truncated);
+    assert_eq!(document.occurrences.into_vec(), all[..first_line].to_vec());
+
+    // Reaching the limit on the last occurrence is not a truncation.
+    let (document, stats) = limited(all.len());
+    assert!(!stats.truncated);
+    assert_eq!(document.occurrences.into_vec(), all);
+
+    let (_, stats) = limited(1000);
+    assert!(!stats.truncated);
+}
+
+#[test]
+fn test_medium_file_compact() -> Result<(), std::io::Error> {
+    let mut contents = String::new();
+    File::open("./testdata/medium.rs")?.read_to_string(&mut contents)?;
+
+    let document = generate("rs", &contents);
+    insta::assert_snapshot!(dump_document_compact(&document));
+
+    Ok(())
+}
+
+#[test]
+fn test_all_files() -> Result<(), std::io::Error> {
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+
+    let dir = read_dir("./src/sg_syntect_document/snapshots/syntect_files/")?;
+    for entry in dir {
+        let entry = entry?;
+        let filepath = entry.path();
+        let mut file = File::open(&filepath)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let query = SourcegraphQuery {
+            extension: filepath.extension().unwrap().to_str().unwrap().to_string(),
+            filepath: filepath.to_str().unwrap().to_string(),
+            code: contents.clone(),
+            ..Default::default()
+        };
+        let document = DocumentGenerator::for_query(&syntax_set, &query)
+            .unwrap()
+            .generate()
+            .unwrap();
+        insta::assert_snapshot!(
+            filepath
+                .to_str()
+                .unwrap()
+                .replace("/src/sg_syntect_document/snapshots/syntect_files", ""),
+            dump_document(&document, &contents)
+        );
+    }
+
+    Ok(())
+}
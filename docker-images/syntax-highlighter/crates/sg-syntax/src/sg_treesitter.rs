@@ -6,10 +6,12 @@ use tree_sitter_highlight::{Highlight, HighlightEvent};
 
 use rocket::serde::json::serde_json::json;
 use rocket::serde::json::Value as JsonValue;
+use serde::Serialize;
+use syntect::parsing::SyntaxSet;
 use tree_sitter_highlight::{HighlightConfiguration, Highlighter as TSHighlighter};
 
-use crate::SourcegraphQuery;
-use sg_lsif::{Document, Occurrence, SyntaxKind};
+use crate::{determine_filetype_with_syntax_set, SourcegraphQuery, SYNTAX_SET};
+use sg_lsif::{Document, Occurrence, SymbolRole, SyntaxKind};
 use sg_macros::include_project_file_optional;
 
 #[rustfmt::skip]
@@ -109,10 +111,21 @@ pub fn jsonify_err(e: impl ToString) -> JsonValue {
 }
 
 pub fn lsif_highlight(q: SourcegraphQuery) -> Result<JsonValue, JsonValue> {
-    let filetype = q
-        .filetype
-        .ok_or_else(|| json!({"error": "Must pass a filetype for /lsif" }))?
-        .to_lowercase();
+    SYNTAX_SET.with(|syntax_set| lsif_highlight_with_syntax_set(q, syntax_set))
+}
+
+/// Like `lsif_highlight`, but queries without a `filetype` detect it from
+/// their `filepath` with the syntaxes of `syntax_set`, see
+/// `determine_filetype_with_syntax_set`.
+pub fn lsif_highlight_with_syntax_set(
+    q: SourcegraphQuery,
+    syntax_set: &SyntaxSet,
+) -> Result<JsonValue, JsonValue> {
+    let filetype = match &q.filetype {
+        Some(filetype) => filetype.to_lowercase(),
+        None if !q.filepath.is_empty() => determine_filetype_with_syntax_set(&q, syntax_set),
+        None => return Err(json!({"error": "Must pass a filetype for /lsif" })),
+    };
 
     match index_language(&filetype, &q.code) {
         Ok(document) => {
@@ -355,6 +368,29 @@ pub fn dump_document(doc: &Document, source: &str) -> String {
     dump_document_range(doc, source, &None)
 }
 
+/// Like `dump_document`, but without the code: one line per occurrence,
+/// sorted by range, like `0:0-0:7 IdentifierKeyword`. This keeps snapshots of
+/// large files small and easy to diff.
+pub fn dump_document_compact(doc: &Document) -> String {
+    let mut occurrences = doc.get_occurrences().to_owned();
+    occurrences.sort_by_key(|o| PackedRange::from_vec(&o.range));
+
+    let mut result = String::new();
+    for occ in occurrences {
+        if occ.syntax_kind == SyntaxKind::UnspecifiedSyntaxKind {
+            continue;
+        }
+
+        let range = PackedRange::from_vec(&occ.range);
+        result.push_str(&format!(
+            "{}:{}-{}:{} {:?}\n",
+            range.start_line, range.start_col, range.end_line, range.end_col, occ.syntax_kind
+        ));
+    }
+
+    result
+}
+
 pub struct FileRange {
     pub start: usize,
     pub end: usize,
@@ -414,6 +450,286 @@ pub fn dump_document_range(doc: &Document, source: &str, file_range: &Option<Fil
     result
 }
 
+/// Serializes `doc` to the SCIP protobuf wire format.
+pub fn document_to_scip_bytes(doc: &Document) -> Vec<u8> {
+    // Documents have no required fields, so writing them can't fail.
+    doc.write_to_bytes()
+        .expect("documents always serialize to bytes")
+}
+
+/// Parses a `Document` from the SCIP protobuf wire format, as written by
+/// `document_to_scip_bytes`.
+pub fn scip_bytes_to_document(bytes: &[u8]) -> Result<Document, protobuf::ProtobufError> {
+    Document::parse_from_bytes(bytes)
+}
+
+#[derive(Serialize)]
+struct JsonOccurrence {
+    start_row: i32,
+    start_col: i32,
+    end_row: i32,
+    end_col: i32,
+    kind: String,
+    text: String,
+}
+
+/// Like `dump_document`, but returns the occurrences as a JSON array of
+/// `{start_row, start_col, end_row, end_col, kind, text}` objects, sorted by
+/// range. Columns are counted in chars, like the ranges of the document.
+pub fn dump_document_json(doc: &Document, source: &str) -> String {
+    let mut occurrences = doc.get_occurrences().to_owned();
+    occurrences.sort_by_key(|o| PackedRange::from_vec(&o.range));
+
+    let lines: Vec<&str> = source.lines().collect();
+    let occurrences: Vec<JsonOccurrence> = occurrences
+        .iter()
+        .filter(|occ| occ.syntax_kind != SyntaxKind::UnspecifiedSyntaxKind)
+        .map(|occ| {
+            let range = PackedRange::from_vec(&occ.range);
+            JsonOccurrence {
+                start_row: range.start_line,
+                start_col: range.start_col,
+                end_row: range.end_line,
+                end_col: range.end_col,
+                kind: format!("{:?}", occ.syntax_kind),
+                text: range_text(&lines, &range),
+            }
+        })
+        .collect();
+
+    serde_json::to_string(&occurrences).expect("occurrences are always valid JSON")
+}
+
+/// Renders `source` with ANSI escape codes that color each occurrence of
+/// `doc` by its kind, e.g. for printing highlighted code in a terminal. Kinds
+/// without a color (see `ansi_color`) are left uncolored.
+pub fn document_to_ansi(doc: &Document, source: &str) -> String {
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let mut colors: Vec<Vec<Option<&str>>> = lines
+        .iter()
+        .map(|line| vec![None; line.trim_end_matches(&['\r', '\n'][..]).chars().count()])
+        .collect();
+
+    for occ in doc.get_occurrences() {
+        let color = match ansi_color(occ.syntax_kind) {
+            Some(color) => color,
+            None => continue,
+        };
+
+        let range = PackedRange::from_vec(&occ.range);
+        for row in range.start_line..=range.end_line {
+            let line_colors = match colors.get_mut(row as usize) {
+                Some(line_colors) => line_colors,
+                None => break,
+            };
+            let start = if row == range.start_line {
+                range.start_col as usize
+            } else {
+                0
+            };
+            let end = if row == range.end_line {
+                range.end_col as usize
+            } else {
+                line_colors.len()
+            };
+            for character in line_colors.iter_mut().take(end).skip(start) {
+                *character = Some(color);
+            }
+        }
+    }
+
+    let mut result = String::new();
+    for (line, line_colors) in lines.iter().zip(colors) {
+        let mut current = None;
+        let mut chars = line.chars();
+        for (color, character) in line_colors.into_iter().zip(chars.by_ref()) {
+            if color != current {
+                result += match color {
+                    Some(color) => color,
+                    None => "\x1b[0m",
+                };
+                current = color;
+            }
+            result.push(character);
+        }
+        if current.is_some() {
+            result += "\x1b[0m";
+        }
+        // The line ending, which is never colored.
+        result.extend(chars);
+    }
+
+    result
+}
+
+/// The ANSI escape code that `document_to_ansi` uses for `kind`.
+fn ansi_color(kind: SyntaxKind) -> Option<&'static str> {
+    use SyntaxKind::*;
+
+    Some(match kind {
+        Comment => "\x1b[90m",
+        IdentifierKeyword => "\x1b[35m",
+        IdentifierBuiltin | IdentifierOperator | Tag | TagDelimiter => "\x1b[31m",
+        StringLiteral | StringLiteralKey | CharacterLiteral => "\x1b[32m",
+        NumericLiteral | BooleanLiteral | IdentifierConstant | IdentifierNull => "\x1b[33m",
+        IdentifierAttribute | TagAttribute => "\x1b[33m",
+        IdentifierFunction
+        | IdentifierFunctionDefinition
+        | IdentifierMacro
+        | IdentifierMacroDefinition => "\x1b[34m",
+        IdentifierType | IdentifierBuiltinType | IdentifierModule => "\x1b[36m",
+        StringLiteralEscape | StringLiteralSpecial | RegexEscape | RegexRepeated
+        | RegexWildcard | RegexDelimiter | RegexJoin => "\x1b[96m",
+        _ => return None,
+    })
+}
+
+/// The token types of `document_to_semantic_tokens`, in the order of their
+/// indices. Clients need them for the legend of the semantic tokens provider.
+pub const SEMANTIC_TOKEN_TYPES: &[&str] = &[
+    "comment",
+    "keyword",
+    "operator",
+    "variable",
+    "parameter",
+    "namespace",
+    "function",
+    "macro",
+    "type",
+    "property",
+    "string",
+    "number",
+    "regexp",
+];
+
+/// The token modifiers of `document_to_semantic_tokens`, in the order of
+/// their bits.
+pub const SEMANTIC_TOKEN_MODIFIERS: &[&str] = &["declaration", "readonly", "defaultLibrary"];
+
+/// The bit of the "declaration" modifier in `SEMANTIC_TOKEN_MODIFIERS`.
+const SEMANTIC_TOKEN_DECLARATION: u32 = 1;
+
+/// Encodes the occurrences of `doc` as LSP semantic tokens: five numbers per
+/// token (the line and start relative to the previous token, the length, the
+/// index in `SEMANTIC_TOKEN_TYPES` and the bits of `SEMANTIC_TOKEN_MODIFIERS`).
+///
+/// Kinds without a token type (see `semantic_token_type`) are skipped.
+/// Occurrences with the `Definition` role are declarations. Tokens can't span
+/// lines, so multi-line occurrences become one token per line of `source`,
+/// where lines end after their last char. Other columns are passed through as
+/// they are, so the document should use the client's position encoding
+/// (UTF-16 by default).
+pub fn document_to_semantic_tokens(doc: &Document, source: &str) -> Vec<u32> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut tokens = Vec::new();
+    for occ in doc.get_occurrences() {
+        let (token_type, mut modifiers) = match semantic_token_type(occ.syntax_kind) {
+            Some(token_type) => token_type,
+            None => continue,
+        };
+        if occ.symbol_roles & SymbolRole::Definition as i32 != 0 {
+            modifiers |= SEMANTIC_TOKEN_DECLARATION;
+        }
+
+        let range = PackedRange::from_vec(&occ.range);
+        for row in range.start_line..=range.end_line {
+            let start = if row == range.start_line {
+                range.start_col
+            } else {
+                0
+            };
+            let end = if row == range.end_line {
+                range.end_col
+            } else {
+                match lines.get(row as usize) {
+                    Some(line) => line.chars().count() as i32,
+                    None => break,
+                }
+            };
+            // E.g. the empty lines of a block comment.
+            if end > start {
+                tokens.push((row, start, end, token_type, modifiers));
+            }
+        }
+    }
+    tokens.sort_by_key(|&(row, start, _, _, _)| (row, start));
+
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut line, mut col) = (0, 0);
+    for (row, start, end, token_type, modifiers) in tokens {
+        let delta_line = (row - line) as u32;
+        let delta_start = if delta_line == 0 { start - col } else { start };
+        data.extend([
+            delta_line,
+            delta_start as u32,
+            (end - start) as u32,
+            token_type,
+            modifiers,
+        ]);
+        line = row;
+        col = start;
+    }
+
+    data
+}
+
+/// The index of the token type in `SEMANTIC_TOKEN_TYPES` and the modifier
+/// bits that `document_to_semantic_tokens` uses for `kind`.
+fn semantic_token_type(kind: SyntaxKind) -> Option<(u32, u32)> {
+    use SyntaxKind::*;
+
+    const DECLARATION: u32 = SEMANTIC_TOKEN_DECLARATION;
+    const READONLY: u32 = 1 << 1;
+    const DEFAULT_LIBRARY: u32 = 1 << 2;
+
+    Some(match kind {
+        Comment => (0, 0),
+        // LSP has no type for booleans.
+        IdentifierKeyword | BooleanLiteral => (1, 0),
+        IdentifierOperator => (2, 0),
+        Identifier | IdentifierLocal | IdentifierShadowed | IdentifierMutableGlobal => (3, 0),
+        IdentifierConstant | IdentifierNull => (3, READONLY),
+        IdentifierBuiltin => (3, DEFAULT_LIBRARY),
+        IdentifierParameter => (4, 0),
+        IdentifierModule => (5, 0),
+        IdentifierFunction => (6, 0),
+        IdentifierFunctionDefinition => (6, DECLARATION),
+        IdentifierMacro => (7, 0),
+        IdentifierMacroDefinition => (7, DECLARATION),
+        IdentifierType => (8, 0),
+        IdentifierBuiltinType => (8, DEFAULT_LIBRARY),
+        IdentifierAttribute | TagAttribute => (9, 0),
+        StringLiteral | StringLiteralEscape | StringLiteralSpecial | StringLiteralKey
+        | CharacterLiteral => (10, 0),
+        NumericLiteral => (11, 0),
+        RegexEscape | RegexRepeated | RegexWildcard | RegexDelimiter | RegexJoin => (12, 0),
+        _ => return None,
+    })
+}
+
+fn range_text(lines: &[&str], range: &PackedRange) -> String {
+    (range.start_line..=range.end_line)
+        .filter_map(|row| {
+            let line = lines.get(row as usize)?;
+            let start = if row == range.start_line {
+                range.start_col as usize
+            } else {
+                0
+            };
+            let text: String = if row == range.end_line {
+                line.chars()
+                    .take(range.end_col as usize)
+                    .skip(start)
+                    .collect()
+            } else {
+                line.chars().skip(start).collect()
+            };
+            Some(text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod test {
     use std::{
@@ -425,6 +741,18 @@ mod test {
 
     use super::*;
 
+    /// Builds a document with an occurrence for each range and kind.
+    fn document_from(occurrences: &[(Vec<i32>, SyntaxKind)]) -> Document {
+        let mut document = Document::new();
+        for (range, kind) in occurrences {
+            let mut occurrence = Occurrence::new();
+            occurrence.range = range.clone();
+            occurrence.syntax_kind = *kind;
+            document.occurrences.push(occurrence);
+        }
+        document
+    }
+
     #[test]
     fn test_highlights_one_comment() -> Result<(), Error> {
         let src = "// Hello World";
@@ -474,6 +802,105 @@ SELECT * FROM my_table
         Ok(())
     }
 
+    #[test]
+    fn test_dump_document_json() {
+        let src = "x := \"é\" // a\n// b\n";
+        let document = document_from(&[
+            (vec![1, 0, 1, 4], SyntaxKind::Comment),
+            (vec![0, 5, 0, 8], SyntaxKind::StringLiteral),
+            (vec![0, 9, 1, 2], SyntaxKind::Comment),
+        ]);
+
+        assert_eq!(
+            dump_document_json(&document, src),
+            concat!(
+                r#"[{"start_row":0,"start_col":5,"end_row":0,"end_col":8,"kind":"StringLiteral","text":"\"é\""},"#,
+                r#"{"start_row":0,"start_col":9,"end_row":1,"end_col":2,"kind":"Comment","text":"// a\n//"},"#,
+                r#"{"start_row":1,"start_col":0,"end_row":1,"end_col":4,"kind":"Comment","text":"// b"}]"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_dump_document_compact() {
+        let document = document_from(&[
+            (vec![1, 0, 4], SyntaxKind::Comment),
+            (vec![0, 0, 7], SyntaxKind::IdentifierKeyword),
+            (vec![0, 9, 1, 2], SyntaxKind::Comment),
+            (vec![0, 8, 9], SyntaxKind::UnspecifiedSyntaxKind),
+        ]);
+
+        assert_eq!(
+            dump_document_compact(&document),
+            "0:0-0:7 IdentifierKeyword\n0:9-1:2 Comment\n1:0-1:4 Comment\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_document_compact_medium_file() -> Result<(), Error> {
+        let src = std::fs::read_to_string("./testdata/medium.go").unwrap();
+        let document = index_language("go", &src)?;
+        insta::assert_snapshot!(dump_document_compact(&document));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_to_ansi() {
+        let src = "package main\r\n/* a\nb */ x\n";
+        let document = document_from(&[
+            (vec![0, 0, 7], SyntaxKind::IdentifierKeyword),
+            (vec![0, 8, 12], SyntaxKind::Identifier),
+            (vec![1, 0, 2, 4], SyntaxKind::Comment),
+        ]);
+
+        assert_eq!(
+            document_to_ansi(&document, src),
+            concat!(
+                "\x1b[35mpackage\x1b[0m main\r\n",
+                "\x1b[90m/* a\x1b[0m\n",
+                "\x1b[90mb */\x1b[0m x\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_document_to_semantic_tokens() {
+        let src = "package /* é\n\n */ main\nfunc main() {}\n";
+        // Out of order, to check that the tokens are sorted.
+        let mut document = document_from(&[
+            (vec![3, 5, 9], SyntaxKind::IdentifierFunctionDefinition),
+            (vec![0, 0, 7], SyntaxKind::IdentifierKeyword),
+            (vec![0, 8, 2, 3], SyntaxKind::Comment),
+            (vec![2, 4, 8], SyntaxKind::Identifier),
+            (vec![3, 9, 10], SyntaxKind::PunctuationBracket),
+        ]);
+        // The package name is a definition, even though its kind isn't.
+        document.occurrences[3].symbol_roles = SymbolRole::Definition as i32;
+
+        assert_eq!(
+            document_to_semantic_tokens(&document, src),
+            vec![
+                0, 0, 7, 1, 0, //
+                0, 8, 4, 0, 0, //
+                2, 0, 3, 0, 0, //
+                0, 4, 4, 3, 1, //
+                1, 5, 4, 6, 1,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scip_bytes_round_trip() -> Result<(), Error> {
+        let src = "package main\n// Hello World\nfunc main() {}\n";
+        let document = index_language("go", src)?;
+
+        let bytes = document_to_scip_bytes(&document);
+        assert_eq!(scip_bytes_to_document(&bytes).unwrap(), document);
+
+        Ok(())
+    }
+
     #[test]
     fn test_all_files() -> Result<(), std::io::Error> {
         let dir = read_dir("./src/snapshots/files/")?;
@@ -492,6 +919,7 @@ SELECT * FROM my_table
                 line_length_limit: None,
                 theme: "".to_string(),
                 code: contents.clone(),
+                ..Default::default()
             });
 
             let document = index_language(filetype, &contents).unwrap();
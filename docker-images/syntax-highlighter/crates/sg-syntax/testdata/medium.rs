@@ -0,0 +1,68 @@
+//! A small key-value store, used as a medium sized fixture.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The maximum number of entries in a `Store`.
+const MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Text(String),
+    Missing,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "\"{}\"", s),
+            Value::Missing => write!(f, "<missing>"),
+        }
+    }
+}
+
+pub struct Store<'a> {
+    name: &'a str,
+    entries: HashMap<String, Value>,
+}
+
+impl<'a> Store<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Store {
+            name,
+            entries: HashMap::new(),
+        }
+    }
+
+    /* Returns false once the store is full. */
+    pub fn insert(&mut self, key: &str, value: Value) -> bool {
+        if self.entries.len() >= MAX_ENTRIES {
+            return false;
+        }
+        self.entries.insert(key.to_string(), value);
+        true
+    }
+
+    pub fn get(&self, key: &str) -> &Value {
+        self.entries.get(key).unwrap_or(&Value::Missing)
+    }
+
+    pub fn total(&self) -> i64 {
+        self.entries
+            .values()
+            .filter_map(|v| match v {
+                Value::Int(n) => Some(*n),
+                _ => None,
+            })
+            .sum()
+    }
+}
+
+fn main() {
+    let mut store = Store::new("example");
+    store.insert("answer", Value::Int(42));
+    store.insert("greeting", Value::Text("hello\n".into()));
+    println!("{}: {} {}", store.name, store.get("answer"), store.total());
+}
@@ -3,16 +3,21 @@
 #[macro_use]
 extern crate rocket;
 
+use std::path::Path;
+
 use rocket::serde::json::{json, Json, Value as JsonValue};
-use sg_syntax::SourcegraphQuery;
+use rocket::State;
+use sg_syntax::{HighlightEngine, SourcegraphQuery};
 
 #[post("/", format = "application/json", data = "<q>")]
-fn syntect(q: Json<SourcegraphQuery>) -> JsonValue {
+fn syntect(q: Json<SourcegraphQuery>, engine: &State<HighlightEngine>) -> JsonValue {
     // TODO(slimsag): In an ideal world we wouldn't be relying on catch_unwind
     // and instead Syntect would return Result types when failures occur. This
     // will require some non-trivial work upstream:
     // https://github.com/trishume/syntect/issues/98
-    let result = std::panic::catch_unwind(|| sg_syntax::syntect_highlight(q.into_inner()));
+    let result = std::panic::catch_unwind(|| {
+        sg_syntax::syntect_highlight_with_syntax_set(q.into_inner(), engine.syntax_set())
+    });
     match result {
         Ok(v) => v,
         Err(_) => json!({"error": "panic while highlighting code", "code": "panic"}),
@@ -20,8 +25,8 @@ fn syntect(q: Json<SourcegraphQuery>) -> JsonValue {
 }
 
 #[post("/lsif", format = "application/json", data = "<q>")]
-fn lsif(q: Json<SourcegraphQuery>) -> JsonValue {
-    match sg_syntax::lsif_highlight(q.into_inner()) {
+fn lsif(q: Json<SourcegraphQuery>, engine: &State<HighlightEngine>) -> JsonValue {
+    match sg_syntax::lsif_highlight_with_syntax_set(q.into_inner(), engine.syntax_set()) {
         Ok(v) => v,
         Err(err) => err,
     }
@@ -39,13 +44,20 @@ fn not_found() -> JsonValue {
 
 #[launch]
 fn rocket() -> _ {
+    // Syntaxes are loaded once here and shared by all requests, since
+    // building a SyntaxSet is expensive.
+    let extra_syntaxes_dir = std::env::var("EXTRA_SYNTAXES_DIR").ok();
+    let syntax_set = sg_syntax::load_syntax_set(extra_syntaxes_dir.as_deref().map(Path::new))
+        .unwrap_or_else(|e| panic!("failed to load EXTRA_SYNTAXES_DIR: {}", e));
+
     // Only list features if QUIET != "true"
     match std::env::var("QUIET") {
         Ok(v) if v == "true" => {}
-        _ => sg_syntax::list_features(),
+        _ => sg_syntax::list_features(&syntax_set),
     };
 
     rocket::build()
+        .manage(HighlightEngine::with_syntax_set(syntax_set))
         .mount("/", routes![syntect, lsif, health])
         .register("/", catchers![not_found])
 }